@@ -0,0 +1,1195 @@
+//! Static analysis of spending policies
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
+
+use miniscript::bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use miniscript::bitcoin::secp256k1::{ecdsa, schnorr};
+use miniscript::bitcoin::util::taproot::TapLeafHash;
+use miniscript::bitcoin::{
+    EcdsaSig, EcdsaSighashType, PublicKey, SchnorrSig, SchnorrSighashType, VarInt, XOnlyPublicKey,
+};
+use miniscript::descriptor::{self, DescriptorPublicKey, ShInner, Wsh, WshInner};
+use miniscript::miniscript::limits::{
+    MAX_OPS_PER_SCRIPT, MAX_SCRIPTSIG_SIZE, MAX_SCRIPT_ELEMENT_SIZE, MAX_SCRIPT_SIZE,
+    MAX_STACK_SIZE, MAX_STANDARD_P2WSH_SCRIPT_SIZE, MAX_STANDARD_P2WSH_STACK_ITEMS,
+};
+use miniscript::policy::compiler::CompilerError;
+use miniscript::policy::{concrete, semantic, Liftable};
+use miniscript::{
+    BareCtx, DescriptorTrait, Legacy, MiniscriptKey, Satisfier, ScriptContext, Segwitv0, Tap,
+    Terminal, ToPublicKey,
+};
+
+use crate::taproot::unspendable_key;
+use crate::time::{is_blocktime_locktime, is_blocktime_seq, number_to_locktime, number_to_seq};
+use crate::util::{derive_descriptor, derive_key};
+use crate::{Descriptor, Error, Policy, Result, Value};
+
+/// A set of conditions that together satisfy a policy. Every condition is a policy leaf: a `pk()`,
+/// an `older()`/`after()` timelock or a hashlock.
+pub type SpendingPath = Vec<Policy>;
+
+pub type SemanticPolicy = semantic::Policy<DescriptorPublicKey>;
+
+/// The maximum number of spending paths that get enumerated. Thresholds have a path for every
+/// combination of their sub-policies, making large ones impractical to enumerate.
+pub const MAX_SPENDING_PATHS: usize = 10_000;
+
+/// Enumerate the minimal sets of conditions that satisfy the policy, in the order they appear in it.
+/// Paths that are a superset of another path are redundant and get removed. Fails if the policy
+/// expands into more than [`MAX_SPENDING_PATHS`] paths.
+pub fn spending_paths(policy: &Policy) -> Result<Vec<SpendingPath>> {
+    let mut paths = dnf(policy)?;
+    for path in &mut paths {
+        path.sort();
+        path.dedup();
+    }
+
+    // Paths are checked from the smallest up, against the smaller minimal paths found so far. Paths of
+    // the same size can only be redundant if they are identical, keeping the first of them.
+    let mut by_size: Vec<usize> = (0..paths.len()).collect();
+    by_size.sort_by_key(|&i| paths[i].len());
+    let mut seen = BTreeSet::new();
+    let mut minimal: Vec<usize> = vec![];
+    for i in by_size {
+        let path = &paths[i];
+        let is_redundant = !seen.insert(path)
+            || minimal
+                .iter()
+                .take_while(|&&j| paths[j].len() < path.len())
+                .any(|&j| is_subset(&paths[j], path));
+        if !is_redundant {
+            minimal.push(i);
+        }
+    }
+    minimal.sort_unstable();
+    Ok(minimal.into_iter().map(|i| paths[i].clone()).collect())
+}
+
+// Expand the policy into its disjunctive normal form, as an OR of ANDs of leaf conditions
+fn dnf(policy: &Policy) -> Result<Vec<SpendingPath>> {
+    Ok(match policy {
+        Policy::Unsatisfiable => vec![],
+        Policy::Trivial => vec![vec![]],
+        Policy::And(subs) => {
+            let subs = subs.iter().map(dnf).collect::<Result<Vec<_>>>()?;
+            product(&subs.iter().collect::<Vec<_>>())?
+        }
+        Policy::Or(subs) => {
+            let mut paths = vec![];
+            for (_, sub) in subs {
+                paths.extend(dnf(sub)?);
+                check_paths_limit(paths.len())?;
+            }
+            paths
+        }
+        Policy::Threshold(k, subs) => {
+            // Unsatisfiable subs have no paths and cannot be part of any combination, while every
+            // other combination contributes at least one path
+            let subs = subs.iter().map(dnf).collect::<Result<Vec<_>>>()?;
+            let subs: Vec<_> = subs.iter().filter(|paths| !paths.is_empty()).collect();
+            check_paths_limit(binomial(subs.len(), *k))?;
+
+            let mut paths = vec![];
+            for combination in combinations(&subs, *k) {
+                paths.extend(product(&combination)?);
+                check_paths_limit(paths.len())?;
+            }
+            paths
+        }
+        leaf => vec![vec![leaf.clone()]],
+    })
+}
+
+// Every way of satisfying all the subs, picking one path from each
+fn product(subs: &[&Vec<SpendingPath>]) -> Result<Vec<SpendingPath>> {
+    let count = subs.iter().try_fold(1usize, |count, sub_paths| {
+        count.checked_mul(sub_paths.len())
+    });
+    check_paths_limit(count.unwrap_or(usize::MAX))?;
+
+    Ok(subs.iter().fold(vec![vec![]], |acc, sub_paths| {
+        acc.iter()
+            .flat_map(|path| {
+                sub_paths.iter().map(move |sub_path| {
+                    let mut path = path.clone();
+                    path.extend(sub_path.iter().cloned());
+                    path
+                })
+            })
+            .collect()
+    }))
+}
+
+// All the `k`-sized combinations of the subs, in order
+fn combinations<T: Clone>(items: &[T], k: usize) -> Vec<Vec<T>> {
+    if k == 0 {
+        return vec![vec![]];
+    }
+    if items.len() < k {
+        return vec![];
+    }
+    let mut with_first = combinations(&items[1..], k - 1);
+    for combination in &mut with_first {
+        combination.insert(0, items[0].clone());
+    }
+    with_first.extend(combinations(&items[1..], k));
+    with_first
+}
+
+// The number of `k`-sized combinations of `n` items. Stops counting once above the spending paths limit.
+fn binomial(n: usize, k: usize) -> usize {
+    if k > n {
+        return 0;
+    }
+    // C(n, i) grows monotonically for i <= n/2
+    let k = k.min(n - k);
+    let mut count = 1;
+    for i in 0..k {
+        count = count * (n - i) / (i + 1);
+        if count > MAX_SPENDING_PATHS {
+            break;
+        }
+    }
+    count
+}
+
+fn check_paths_limit(count: usize) -> Result<()> {
+    ensure!(
+        count <= MAX_SPENDING_PATHS,
+        Error::TooManySpendingPaths(MAX_SPENDING_PATHS)
+    );
+    Ok(())
+}
+
+// Both paths are expected to be sorted
+fn is_subset(subset: &[Policy], superset: &[Policy]) -> bool {
+    subset.iter().all(|c| superset.binary_search(c).is_ok())
+}
+
+/// Lift a descriptor into the policy of its spending conditions. Branch probabilities are not
+/// encoded in the script, so all the `Or` branches get an equal weight.
+pub fn lift_descriptor(desc: &Descriptor) -> Policy {
+    match desc {
+        Descriptor::Bare(bare) => lift_miniscript(bare.as_inner()),
+        Descriptor::Pkh(pkh) => Policy::Key(pkh.as_inner().clone()),
+        Descriptor::Wpkh(wpkh) => Policy::Key(wpkh.as_inner().clone()),
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::Wsh(wsh) => lift_wsh(wsh),
+            ShInner::Wpkh(wpkh) => Policy::Key(wpkh.as_inner().clone()),
+            ShInner::SortedMulti(smv) => lift_multi(smv.k, &smv.pks),
+            ShInner::Ms(ms) => lift_miniscript(ms),
+        },
+        Descriptor::Wsh(wsh) => lift_wsh(wsh),
+        // The internal key can spend as an alternative to any of the script leaves, unless it is the
+        // unspendable key used when there is no key path
+        Descriptor::Tr(tr) => {
+            let key_path = match tr.internal_key() {
+                pk if *pk == unspendable_key() => Policy::Unsatisfiable,
+                pk => Policy::Key(pk.clone()),
+            };
+            tr.iter_scripts()
+                .fold(key_path, |policy, (_, ms)| or(policy, lift_miniscript(ms)))
+        }
+    }
+}
+
+fn lift_wsh(wsh: &Wsh<DescriptorPublicKey>) -> Policy {
+    match wsh.as_inner() {
+        WshInner::SortedMulti(smv) => lift_multi(smv.k, &smv.pks),
+        WshInner::Ms(ms) => lift_miniscript(ms),
+    }
+}
+
+/// Lift a miniscript into the policy of its spending conditions, in any script context
+pub fn lift_miniscript<Ctx: ScriptContext>(
+    ms: &miniscript::Miniscript<DescriptorPublicKey, Ctx>,
+) -> Policy {
+    let lift = |sub: &Arc<miniscript::Miniscript<DescriptorPublicKey, Ctx>>| lift_miniscript(sub);
+    match &ms.node {
+        Terminal::True => Policy::Trivial,
+        Terminal::False => Policy::Unsatisfiable,
+        Terminal::PkK(pk) | Terminal::PkH(pk) => Policy::Key(pk.clone()),
+        Terminal::After(n) => Policy::After(*n),
+        Terminal::Older(n) => Policy::Older(*n),
+        Terminal::Sha256(h) => Policy::Sha256(*h),
+        Terminal::Hash256(h) => Policy::Hash256(*h),
+        Terminal::Ripemd160(h) => Policy::Ripemd160(*h),
+        Terminal::Hash160(h) => Policy::Hash160(*h),
+        Terminal::Alt(sub)
+        | Terminal::Swap(sub)
+        | Terminal::Check(sub)
+        | Terminal::DupIf(sub)
+        | Terminal::Verify(sub)
+        | Terminal::NonZero(sub)
+        | Terminal::ZeroNotEqual(sub) => lift(sub),
+        Terminal::AndV(a, b) | Terminal::AndB(a, b) => and(lift(a), lift(b)),
+        Terminal::AndOr(a, b, c) => or(and(lift(a), lift(b)), lift(c)),
+        Terminal::OrB(a, b) | Terminal::OrC(a, b) | Terminal::OrD(a, b) | Terminal::OrI(a, b) => {
+            or(lift(a), lift(b))
+        }
+        Terminal::Thresh(k, subs) => Policy::Threshold(*k, subs.iter().map(lift).collect()),
+        Terminal::Multi(k, pks) | Terminal::MultiA(k, pks) => lift_multi(*k, pks),
+    }
+}
+
+fn lift_multi(k: usize, pks: &[DescriptorPublicKey]) -> Policy {
+    Policy::Threshold(k, pks.iter().cloned().map(Policy::Key).collect())
+}
+
+// The constant `1`/`0` fragments used by the `t:`, `l:`, `u:` wrappers and `and_n` are dropped
+fn and(a: Policy, b: Policy) -> Policy {
+    match (a, b) {
+        (Policy::Trivial, other) | (other, Policy::Trivial) => other,
+        (Policy::Unsatisfiable, _) | (_, Policy::Unsatisfiable) => Policy::Unsatisfiable,
+        (a, b) => Policy::And(vec![a, b]),
+    }
+}
+
+fn or(a: Policy, b: Policy) -> Policy {
+    match (a, b) {
+        (Policy::Unsatisfiable, other) | (other, Policy::Unsatisfiable) => other,
+        (a, b) => Policy::Or(vec![(1, a), (1, b)]),
+    }
+}
+
+/// Lift a policy, miniscript or descriptor into its semantic policy, keeping only the spending
+/// conditions. Nested thresholds are flattened and sorted into a canonical form, and thresholds of
+/// two sub-policies are turned into `and`/`or`, matching the policies produced by `&&` and `||`.
+pub fn lift(value: Value) -> Result<Policy> {
+    Ok(from_semantic(into_concrete(value)?.lift()?.sorted()))
+}
+
+/// The spending conditions (keys, hashes and timelocks) used by a policy, miniscript or descriptor,
+/// deduplicated and in order of appearance
+pub fn conditions(value: Value) -> Result<Vec<Policy>> {
+    fn collect(policy: &Policy, conditions: &mut Vec<Policy>) {
+        match policy {
+            Policy::And(_) | Policy::Or(_) | Policy::Threshold(..) => {
+                for sub in sub_policies(policy) {
+                    collect(sub, conditions);
+                }
+            }
+            Policy::Unsatisfiable | Policy::Trivial => {}
+            condition if !conditions.contains(condition) => conditions.push(condition.clone()),
+            _ => {}
+        }
+    }
+    let mut conditions = vec![];
+    collect(&into_concrete(value)?, &mut conditions);
+    Ok(conditions)
+}
+
+fn into_concrete(value: Value) -> Result<Policy> {
+    // Miniscripts are lifted using lift_miniscript(), as the rust-miniscript lifting swaps the
+    // branches of andor()
+    Ok(match value {
+        Value::Policy(_) | Value::Array(_) => value.into_policy()?,
+        Value::Miniscript(ms) => lift_miniscript(&ms),
+        other => lift_descriptor(&other.into_desc()?),
+    })
+}
+
+fn from_semantic(semantic: SemanticPolicy) -> Policy {
+    match semantic {
+        SemanticPolicy::Unsatisfiable => Policy::Unsatisfiable,
+        SemanticPolicy::Trivial => Policy::Trivial,
+        SemanticPolicy::KeyHash(pk) => Policy::Key(pk),
+        SemanticPolicy::After(n) => Policy::After(n),
+        SemanticPolicy::Older(n) => Policy::Older(n),
+        SemanticPolicy::Sha256(h) => Policy::Sha256(h),
+        SemanticPolicy::Hash256(h) => Policy::Hash256(h),
+        SemanticPolicy::Ripemd160(h) => Policy::Ripemd160(h),
+        SemanticPolicy::Hash160(h) => Policy::Hash160(h),
+        SemanticPolicy::Threshold(k, subs) => {
+            let subs: Vec<_> = subs.into_iter().map(from_semantic).collect();
+            match (k, subs.len()) {
+                (2, 2) => Policy::And(subs),
+                (1, 2) => Policy::Or(subs.into_iter().map(|sub| (1, sub)).collect()),
+                _ => Policy::Threshold(k, subs),
+            }
+        }
+    }
+}
+
+// Use and()/or() for pruned thresholds that are equivalent to them, like from_semantic() does for
+// lifted ones. n-of-n is nested as binary and()s, which the compiler requires.
+fn threshold(k: usize, mut subs: Vec<Policy>) -> Policy {
+    match (k, subs.len()) {
+        (1, 1) => subs.remove(0),
+        (1, 2) => Policy::Or(subs.into_iter().map(|sub| (1, sub)).collect()),
+        (k, n) if k == n && n >= 2 => {
+            let last = subs.pop().unwrap();
+            subs.into_iter()
+                .rev()
+                .fold(last, |acc, sub| Policy::And(vec![sub, acc]))
+        }
+        _ => Policy::Threshold(k, subs),
+    }
+}
+
+/// A set of conditions that satisfies one of two compared policies but not the other
+#[derive(Debug, Clone)]
+pub struct Counterexample {
+    pub conditions: SpendingPath,
+    /// Whether it is the first policy that the conditions satisfy, or the second
+    pub satisfies_first: bool,
+}
+
+/// Check whether two policies have identical satisfying sets, returning a counterexample if they don't.
+/// A timelock is considered to imply the weaker timelocks of the same type, such that `older(20)`
+/// satisfies `older(10)`. Policies should be [`lift()`]ed first to compare them semantically.
+pub fn counterexample(a: &Policy, b: &Policy) -> Result<Option<Counterexample>> {
+    let (a_paths, b_paths) = (spending_paths(a)?, spending_paths(b)?);
+    // Comparing the minimal paths is sufficient, as policies are monotone
+    let unsatisfied = |paths: &[SpendingPath], others: &[SpendingPath]| {
+        paths
+            .iter()
+            .find(|path| !others.iter().any(|other| satisfies(path, other)))
+            .cloned()
+    };
+    Ok(if let Some(conditions) = unsatisfied(&a_paths, &b_paths) {
+        Some(Counterexample {
+            conditions,
+            satisfies_first: true,
+        })
+    } else {
+        unsatisfied(&b_paths, &a_paths).map(|conditions| Counterexample {
+            conditions,
+            satisfies_first: false,
+        })
+    })
+}
+
+// Whether the conditions satisfy all of the path conditions
+fn satisfies(conditions: &[Policy], path: &[Policy]) -> bool {
+    path.iter()
+        .all(|required| conditions.iter().any(|c| implies(c, required)))
+}
+
+fn implies(condition: &Policy, required: &Policy) -> bool {
+    match (condition, required) {
+        (Policy::Older(n), Policy::Older(r)) => {
+            is_blocktime_seq(*n) == is_blocktime_seq(*r) && n >= r
+        }
+        (Policy::After(n), Policy::After(r)) => {
+            is_blocktime_locktime(*n) == is_blocktime_locktime(*r) && n >= r
+        }
+        (condition, required) => condition == required,
+    }
+}
+
+/// Prune the policy down to the branches that are satisfiable once the given relative age (as a BIP68
+/// sequence number) is reached. `older()` timelocks of the other type (by-blocktime vs by-blockheight)
+/// are considered unsatisfied.
+pub fn at_age(policy: &Policy, age: u32) -> Policy {
+    let reached = Policy::Older(age);
+    prune(policy, &|c| {
+        !matches!(c, Policy::Older(_)) || implies(&reached, c)
+    })
+}
+
+/// Prune the policy down to the branches that are satisfiable at the given absolute block height or
+/// unix timestamp. `after()` timelocks of the other type are considered unsatisfied.
+pub fn at_time(policy: &Policy, locktime: u32) -> Policy {
+    let reached = Policy::After(locktime);
+    prune(policy, &|c| {
+        !matches!(c, Policy::After(_)) || implies(&reached, c)
+    })
+}
+
+// Replace the leaf conditions that cannot be satisfied with Unsatisfiable, and remove the branches
+// that no longer have enough satisfiable sub-policies
+fn prune(policy: &Policy, is_satisfiable: &dyn Fn(&Policy) -> bool) -> Policy {
+    let prune_subs = |subs: &[Policy]| -> Vec<Policy> {
+        subs.iter()
+            .map(|sub| prune(sub, is_satisfiable))
+            .filter(|sub| *sub != Policy::Unsatisfiable)
+            .collect()
+    };
+    match policy {
+        Policy::And(subs) => {
+            let pruned = prune_subs(subs);
+            match pruned.len() == subs.len() {
+                true => Policy::And(pruned),
+                false => Policy::Unsatisfiable,
+            }
+        }
+        Policy::Or(subs) => {
+            let mut pruned: Vec<_> = subs
+                .iter()
+                .map(|(prob, sub)| (*prob, prune(sub, is_satisfiable)))
+                .filter(|(_, sub)| *sub != Policy::Unsatisfiable)
+                .collect();
+            match pruned.len() {
+                0 => Policy::Unsatisfiable,
+                1 => pruned.remove(0).1,
+                _ => Policy::Or(pruned),
+            }
+        }
+        Policy::Threshold(k, subs) => {
+            let pruned = prune_subs(subs);
+            match pruned.len() < *k {
+                true => Policy::Unsatisfiable,
+                false => threshold(*k, pruned),
+            }
+        }
+        leaf if is_satisfiable(leaf) => leaf.clone(),
+        _ => Policy::Unsatisfiable,
+    }
+}
+
+/// The keys, hash preimages and time available for spending
+#[derive(Debug, Clone, Default)]
+pub struct Resources {
+    pub keys: Vec<DescriptorPublicKey>,
+    pub preimages: Vec<Vec<u8>>,
+    /// The relative age of the coins, as a BIP68 sequence number
+    pub age: Option<u32>,
+    /// The absolute block height or unix timestamp
+    pub time: Option<u32>,
+}
+
+impl Resources {
+    /// Whether the resources satisfy the spending path condition
+    pub fn satisfies(&self, condition: &Policy) -> bool {
+        let has_preimage =
+            |matches: &dyn Fn(&[u8]) -> bool| self.preimages.iter().any(|p| matches(p));
+        match condition {
+            Policy::Key(pk) => self.keys.contains(pk),
+            Policy::Sha256(h) => has_preimage(&|p| sha256::Hash::hash(p) == *h),
+            Policy::Hash256(h) => has_preimage(&|p| sha256d::Hash::hash(p) == *h),
+            Policy::Ripemd160(h) => has_preimage(&|p| ripemd160::Hash::hash(p) == *h),
+            Policy::Hash160(h) => has_preimage(&|p| hash160::Hash::hash(p) == *h),
+            Policy::Older(_) => {
+                matches!(self.age, Some(age) if implies(&Policy::Older(age), condition))
+            }
+            Policy::After(_) => {
+                matches!(self.time, Some(time) if implies(&Policy::After(time), condition))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether a policy, miniscript or descriptor can be spent using the available resources
+#[derive(Debug, Clone)]
+pub enum Spendability {
+    /// Spendable using the cheapest satisfiable path, with its satisfaction weight when it is known
+    Spendable(SpendingPath, Option<usize>),
+    /// Not spendable. Has the missing conditions of the paths that are missing the fewest conditions.
+    /// For multisig, these are given as a threshold of the keys that are still needed.
+    Missing(Vec<SpendingPath>),
+}
+
+/// Simulate spending a policy, miniscript or descriptor using the available resources
+pub fn can_spend(value: Value, resources: &Resources) -> Result<Spendability> {
+    // Policies are compiled to get the satisfaction weights, but can be checked without them
+    let (policy, desc) = match value {
+        Value::Policy(_) | Value::Array(_) => {
+            let policy = value.into_policy()?;
+            let desc = Value::from(policy.clone()).into_desc().ok();
+            (policy, desc)
+        }
+        other => {
+            let desc = other.into_desc()?;
+            (lift_descriptor(&desc), Some(desc))
+        }
+    };
+    let cost = desc
+        .map(|desc| cost(&desc, MAX_WEIGHED_PATHS))
+        .transpose()?;
+    let paths = match cost.map(|cost| cost.paths) {
+        Some(PathWeights::Paths(paths)) => paths,
+        Some(PathWeights::Multi(k, pks, weight)) => {
+            return Ok(can_spend_multi(k, pks, weight, resources))
+        }
+        Some(PathWeights::TooMany) | None => spending_paths(&policy)?
+            .into_iter()
+            .map(|path| (path, None))
+            .collect(),
+    };
+
+    let (satisfied, unsatisfied): (Vec<_>, Vec<_>) = paths
+        .into_iter()
+        .partition(|(path, _)| path.iter().all(|c| resources.satisfies(c)));
+
+    // Paths with an unknown weight are the least preferred, followed by the ones with more conditions
+    let cheapest = satisfied
+        .into_iter()
+        .min_by_key(|(path, weight)| (weight.is_none(), *weight, path.len()));
+    if let Some((path, weight)) = cheapest {
+        return Ok(Spendability::Spendable(path, weight));
+    }
+
+    let missing: Vec<SpendingPath> = unsatisfied
+        .into_iter()
+        .map(|(path, _)| {
+            path.into_iter()
+                .filter(|c| !resources.satisfies(c))
+                .collect()
+        })
+        .collect();
+    let fewest = missing.iter().map(Vec::len).min().unwrap_or(0);
+    Ok(Spendability::Missing(
+        missing.into_iter().filter(|m| m.len() == fewest).collect(),
+    ))
+}
+
+// Multisig is spendable with any `k` of the available keys. Otherwise, the missing keys are given as a
+// threshold of the keys that still need to be provided.
+fn can_spend_multi(
+    k: usize,
+    pks: Vec<DescriptorPublicKey>,
+    weight: Option<usize>,
+    resources: &Resources,
+) -> Spendability {
+    let (available, missing): (Vec<_>, Vec<_>) = pks
+        .into_iter()
+        .map(Policy::Key)
+        .partition(|pk| resources.satisfies(pk));
+    if available.len() >= k {
+        return Spendability::Spendable(available[..k].to_vec(), weight);
+    }
+    let needed = k - available.len();
+    Spendability::Missing(vec![match needed == missing.len() {
+        true => missing,
+        false => vec![Policy::Threshold(needed, missing)],
+    }])
+}
+
+/// The resources needed to spend a descriptor, using the miniscript satisfaction cost model where
+/// signatures are assumed to be 73 bytes.
+#[derive(Debug, Clone)]
+pub struct Cost {
+    /// The size of the executed script (the witnessScript, redeemScript or scriptPubKey, or the implied
+    /// P2PKH script for P2WPKH), in bytes
+    pub script_size: usize,
+    /// The number of non-push opcodes executed by the most expensive satisfaction
+    pub op_count: Option<usize>,
+    /// The maximum weight of a satisfaction, including the scriptSig and witness
+    pub max_weight: Option<usize>,
+    /// The weight of the satisfaction of the spending paths
+    pub paths: PathWeights,
+}
+
+/// The satisfaction weights of the spending paths of a descriptor
+#[derive(Debug, Clone)]
+pub enum PathWeights {
+    /// The weight of every spending path. Paths that cannot be satisfied non-malleably get a `None`.
+    Paths(Vec<(SpendingPath, Option<usize>)>),
+    /// A k-of-n multisig, where every combination of `k` keys is satisfied with the same weight
+    Multi(usize, Vec<DescriptorPublicKey>, Option<usize>),
+    /// There are more spending paths than the limit they get weighed up to
+    TooMany,
+}
+
+/// The maximum number of spending paths weighed by [`cost()`] when called from Minsc code. Every path
+/// gets satisfied separately, which is considerably slower than enumerating it.
+pub const MAX_WEIGHED_PATHS: usize = 1_000;
+
+/// Compute the cost of a descriptor, weighing up to `max_paths` of its spending paths
+pub fn cost(desc: &Descriptor, max_paths: usize) -> Result<Cost> {
+    let derived = derive_descriptor(desc, 0)?;
+
+    let op_count = match desc {
+        Descriptor::Bare(bare) => bare.as_inner().ext.ops_count_sat,
+        // OP_DUP OP_HASH160 OP_EQUALVERIFY OP_CHECKSIG
+        Descriptor::Pkh(_) | Descriptor::Wpkh(_) => Some(4),
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::Wsh(wsh) => wsh_op_count(wsh),
+            ShInner::Wpkh(_) => Some(4),
+            ShInner::SortedMulti(smv) => Some(smv.pks.len() + 1),
+            ShInner::Ms(ms) => ms.ext.ops_count_sat,
+        },
+        Descriptor::Wsh(wsh) => wsh_op_count(wsh),
+        // Key path spends execute no script, script path spends execute the leaf script
+        Descriptor::Tr(tr) => Some(
+            tr.iter_scripts()
+                .filter_map(|(_, ms)| ms.ext.ops_count_sat)
+                .max()
+                .unwrap_or(0),
+        ),
+    };
+
+    let script_size = match &derived {
+        // The largest tapscript leaf, or none for key-only taproot
+        descriptor::Descriptor::Tr(tr) => tr
+            .iter_scripts()
+            .map(|(_, ms)| ms.script_size())
+            .max()
+            .unwrap_or(0),
+        other => other.script_code()?.len(),
+    };
+
+    let paths = if let Some((k, pks)) = multisig(desc) {
+        let path: SpendingPath = pks[..k].iter().cloned().map(Policy::Key).collect();
+        PathWeights::Multi(k, pks.to_vec(), path_weight(&derived, &path))
+    } else {
+        match spending_paths(&lift_descriptor(desc)) {
+            Ok(paths) if paths.len() <= max_paths => PathWeights::Paths(
+                paths
+                    .into_iter()
+                    .map(|path| {
+                        let weight = path_weight(&derived, &path);
+                        (path, weight)
+                    })
+                    .collect(),
+            ),
+            _ => PathWeights::TooMany,
+        }
+    };
+
+    let max_weight = desc.max_satisfaction_weight().ok();
+    // miniscript leaves the push opcode of the public key out of the pkh() scriptSig size
+    let max_weight = match desc {
+        Descriptor::Pkh(_) => max_weight.map(|weight| weight + 4),
+        _ => max_weight,
+    };
+
+    Ok(Cost {
+        script_size,
+        op_count,
+        max_weight,
+        paths,
+    })
+}
+
+// OP_CHECKMULTISIG counts as an op for itself and for every public key
+fn wsh_op_count(wsh: &Wsh<DescriptorPublicKey>) -> Option<usize> {
+    match wsh.as_inner() {
+        WshInner::SortedMulti(smv) => Some(smv.pks.len() + 1),
+        WshInner::Ms(ms) => ms.ext.ops_count_sat,
+    }
+}
+
+// The threshold and keys of multi() and sortedmulti() descriptors
+fn multisig(desc: &Descriptor) -> Option<(usize, &[DescriptorPublicKey])> {
+    fn multi_node<Ctx: ScriptContext>(
+        ms: &miniscript::Miniscript<DescriptorPublicKey, Ctx>,
+    ) -> Option<(usize, &[DescriptorPublicKey])> {
+        match &ms.node {
+            Terminal::Multi(k, pks) => Some((*k, pks)),
+            _ => None,
+        }
+    }
+    fn wsh_multi(wsh: &Wsh<DescriptorPublicKey>) -> Option<(usize, &[DescriptorPublicKey])> {
+        match wsh.as_inner() {
+            WshInner::SortedMulti(smv) => Some((smv.k, &smv.pks)),
+            WshInner::Ms(ms) => multi_node(ms),
+        }
+    }
+    match desc {
+        Descriptor::Bare(bare) => multi_node(bare.as_inner()),
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::Wsh(wsh) => wsh_multi(wsh),
+            ShInner::SortedMulti(smv) => Some((smv.k, &smv.pks)),
+            ShInner::Ms(ms) => multi_node(ms),
+            ShInner::Wpkh(_) => None,
+        },
+        Descriptor::Wsh(wsh) => wsh_multi(wsh),
+        _ => None,
+    }
+}
+
+// Satisfy the descriptor using dummy signatures and preimages for the path conditions only, and
+// measure the resulting scriptSig and witness
+fn path_weight(desc: &descriptor::Descriptor<PublicKey>, path: &[Policy]) -> Option<usize> {
+    let satisfier = PathSatisfier::new(desc, path).ok()?;
+    let (witness, script_sig) = desc.get_satisfaction(satisfier).ok()?;
+
+    let varint_len = |n: usize| VarInt(n as u64).len();
+    let mut weight = 4 * (varint_len(script_sig.len()) + script_sig.len());
+    if !witness.is_empty() {
+        weight += varint_len(witness.len());
+        weight += witness
+            .iter()
+            .map(|el| varint_len(el.len()) + el.len())
+            .sum::<usize>();
+    }
+    Some(weight)
+}
+
+// A 71 bytes DER-encoded signature with a high R and a low S. With the sighash flag and the push
+// prefix, this matches the 73 bytes signatures assumed by the cost model.
+fn dummy_ecdsa_sig() -> EcdsaSig {
+    let mut compact = [1u8; 64];
+    compact[0] = 0x80;
+    EcdsaSig {
+        sig: ecdsa::Signature::from_compact(&compact).expect("valid signature"),
+        hash_ty: EcdsaSighashType::All,
+    }
+}
+
+// A 64 bytes schnorr signature with an explicit sighash flag, for the 65 bytes assumed by the cost model
+fn dummy_schnorr_sig() -> SchnorrSig {
+    SchnorrSig {
+        sig: schnorr::Signature::from_slice(&[1u8; 64]).expect("valid signature"),
+        hash_ty: SchnorrSighashType::All,
+    }
+}
+
+type DerivedPolicy = concrete::Policy<PublicKey>;
+
+// Satisfies the path conditions, with the keys derived the same way as the descriptor's
+struct PathSatisfier {
+    path: Vec<DerivedPolicy>,
+    key_spend: bool,
+}
+
+impl PathSatisfier {
+    fn new(desc: &descriptor::Descriptor<PublicKey>, path: &[Policy]) -> Result<Self> {
+        let path = path
+            .iter()
+            .map(|condition| condition.translate_pk(|pk| derive_key(pk, 0)))
+            .collect::<Result<Vec<_>>>()?;
+        // Taproot key path spends are signed with the internal key
+        let key_spend = match desc {
+            descriptor::Descriptor::Tr(tr) => {
+                path.contains(&DerivedPolicy::Key(*tr.internal_key()))
+            }
+            _ => false,
+        };
+        Ok(PathSatisfier { path, key_spend })
+    }
+
+    fn has(&self, condition: &DerivedPolicy) -> bool {
+        self.path.contains(condition)
+    }
+
+    fn find_pkh(&self, pkh: &hash160::Hash) -> Option<PublicKey> {
+        self.path.iter().find_map(|condition| match condition {
+            DerivedPolicy::Key(pk) if pk.to_pubkeyhash() == *pkh => Some(*pk),
+            _ => None,
+        })
+    }
+}
+
+impl Satisfier<PublicKey> for PathSatisfier {
+    fn lookup_ecdsa_sig(&self, pk: &PublicKey) -> Option<EcdsaSig> {
+        self.has(&DerivedPolicy::Key(*pk)).then(dummy_ecdsa_sig)
+    }
+
+    fn lookup_tap_key_spend_sig(&self) -> Option<SchnorrSig> {
+        self.key_spend.then(dummy_schnorr_sig)
+    }
+
+    fn lookup_tap_leaf_script_sig(&self, pk: &PublicKey, _: &TapLeafHash) -> Option<SchnorrSig> {
+        self.has(&DerivedPolicy::Key(*pk)).then(dummy_schnorr_sig)
+    }
+
+    fn lookup_pkh_pk(&self, pkh: &hash160::Hash) -> Option<PublicKey> {
+        self.find_pkh(pkh)
+    }
+
+    fn lookup_pkh_ecdsa_sig(&self, pkh: &hash160::Hash) -> Option<(PublicKey, EcdsaSig)> {
+        self.find_pkh(pkh).map(|pk| (pk, dummy_ecdsa_sig()))
+    }
+
+    fn lookup_pkh_tap_leaf_script_sig(
+        &self,
+        (pkh, _): &(hash160::Hash, TapLeafHash),
+    ) -> Option<(XOnlyPublicKey, SchnorrSig)> {
+        self.find_pkh(pkh)
+            .map(|pk| (pk.to_x_only_pubkey(), dummy_schnorr_sig()))
+    }
+
+    fn lookup_sha256(&self, h: sha256::Hash) -> Option<[u8; 32]> {
+        self.has(&DerivedPolicy::Sha256(h)).then_some([0; 32])
+    }
+
+    fn lookup_hash256(&self, h: sha256d::Hash) -> Option<[u8; 32]> {
+        self.has(&DerivedPolicy::Hash256(h)).then_some([0; 32])
+    }
+
+    fn lookup_ripemd160(&self, h: ripemd160::Hash) -> Option<[u8; 32]> {
+        self.has(&DerivedPolicy::Ripemd160(h)).then_some([0; 32])
+    }
+
+    fn lookup_hash160(&self, h: hash160::Hash) -> Option<[u8; 32]> {
+        self.has(&DerivedPolicy::Hash160(h)).then_some([0; 32])
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.has(&DerivedPolicy::Older(n))
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.has(&DerivedPolicy::After(n))
+    }
+}
+
+/// The type of problem reported by [`check()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckKind {
+    /// The policy is invalid, or has conditions that can never be satisfied or can be satisfied by anyone
+    Sanity,
+    /// Third parties could modify the satisfaction without invalidating it
+    Malleability,
+    /// Spending would violate the relay policy of Bitcoin Core nodes
+    Standardness,
+    /// Spending would violate the consensus rules, making the coins unspendable
+    Consensus,
+}
+
+impl fmt::Display for CheckKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CheckKind::Sanity => "sanity",
+            CheckKind::Malleability => "malleability",
+            CheckKind::Standardness => "standardness",
+            CheckKind::Consensus => "consensus",
+        })
+    }
+}
+
+/// A problem found by [`check()`], along with the sub-policy, miniscript fragment or spending path
+/// that causes it
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub kind: CheckKind,
+    pub message: String,
+    pub culprit: String,
+}
+
+impl Finding {
+    fn new(kind: CheckKind, message: impl Into<String>, culprit: impl fmt::Display) -> Self {
+        Finding {
+            kind,
+            message: message.into(),
+            culprit: culprit.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}\n  in {}", self.kind, self.message, self.culprit)
+    }
+}
+
+/// Run sanity, malleability, standardness and consensus checks on a policy, miniscript or descriptor.
+/// Policies are checked for use in wsh(), and get compiled to check the resulting miniscript.
+pub fn check(value: Value) -> Result<Vec<Finding>> {
+    Ok(match value {
+        Value::Policy(_) | Value::Array(_) => check_policy(&value.into_policy()?),
+        Value::Miniscript(ms) => check_miniscript(&ms, &segwit_limits()),
+        other => check_descriptor(&other.into_desc()?),
+    })
+}
+
+pub fn check_policy(policy: &Policy) -> Vec<Finding> {
+    let mut findings = vec![];
+    check_policy_nodes(policy, &mut findings);
+    findings.extend(check_keys(policy));
+
+    if policy.check_timelocks().is_err() {
+        let culprit = innermost_policy(policy, &|p| p.check_timelocks().is_err());
+        findings.push(mixed_timelocks(culprit));
+    }
+
+    let (safe, non_malleable) = policy.is_safe_nonmalleable();
+    if !safe {
+        findings.extend(check_sigless(policy));
+    } else if !non_malleable {
+        let culprit = innermost_policy(policy, &|p| !p.is_safe_nonmalleable().1);
+        findings.push(Finding::new(
+            CheckKind::Malleability,
+            "No non-malleable compilation exists, a signature-less branch needs to be combined with a key",
+            culprit,
+        ));
+    }
+
+    // Compilation requires all the other checks to pass
+    if findings.is_empty() {
+        match policy.compile::<Segwitv0>() {
+            Ok(ms) => findings.extend(check_miniscript(&ms, &segwit_limits())),
+            Err(CompilerError::LimitsExceeded) => {
+                let exceeds = |p: &Policy| {
+                    matches!(p.compile::<Segwitv0>(), Err(CompilerError::LimitsExceeded))
+                };
+                findings.push(Finding::new(
+                    CheckKind::Standardness,
+                    format!(
+                        "No compilation within the resource limits ({} ops, {} bytes witness script, {} witness elements)",
+                        MAX_OPS_PER_SCRIPT, MAX_STANDARD_P2WSH_SCRIPT_SIZE, MAX_STANDARD_P2WSH_STACK_ITEMS
+                    ),
+                    innermost_policy(policy, &exceeds),
+                ));
+            }
+            Err(e) => findings.push(Finding::new(CheckKind::Sanity, e.to_string(), policy)),
+        }
+    }
+    findings
+}
+
+pub fn check_descriptor(desc: &Descriptor) -> Vec<Finding> {
+    let check_wsh = |wsh: &Wsh<_>| match wsh.as_inner() {
+        WshInner::Ms(ms) => check_miniscript(ms, &segwit_limits()),
+        WshInner::SortedMulti(_) => check_keys(&lift_descriptor(desc)),
+    };
+    match desc {
+        Descriptor::Bare(bare) => check_miniscript(bare.as_inner(), &bare_limits()),
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::Ms(ms) => check_miniscript(ms, &legacy_limits()),
+            ShInner::Wsh(wsh) => check_wsh(wsh),
+            _ => check_keys(&lift_descriptor(desc)),
+        },
+        Descriptor::Wsh(wsh) => check_wsh(wsh),
+        // Keys may be reused in different leaves, that are checked separately
+        Descriptor::Tr(tr) => tr
+            .iter_scripts()
+            .flat_map(|(_, ms)| check_miniscript(ms, &tap_limits()))
+            .collect(),
+        _ => check_keys(&lift_descriptor(desc)),
+    }
+}
+
+/// Check a miniscript against the resource limits of its script context
+pub fn check_miniscript<Ctx: ScriptContext>(
+    ms: &miniscript::Miniscript<DescriptorPublicKey, Ctx>,
+    limits: &[Limit<Ctx>],
+) -> Vec<Finding> {
+    let policy = lift_miniscript(ms);
+    let mut findings = check_keys(&policy);
+
+    if ms.has_mixed_timelocks() {
+        let culprit = innermost_fragment(ms, &|f| f.has_mixed_timelocks());
+        findings.push(mixed_timelocks(culprit));
+    }
+
+    if !ms.requires_sig() {
+        findings.extend(check_sigless(&policy));
+    }
+    if !ms.is_non_malleable() {
+        let culprit = innermost_fragment(ms, &|f| !f.is_non_malleable());
+        findings.push(Finding::new(
+            CheckKind::Malleability,
+            "Malleable fragment, third parties can modify the satisfaction",
+            culprit,
+        ));
+    }
+    for fragment in ms.iter() {
+        if let Err(e) = Ctx::check_terminal_non_malleable(&fragment.node) {
+            findings.push(Finding::new(
+                CheckKind::Malleability,
+                e.to_string(),
+                fragment,
+            ));
+        }
+    }
+
+    for limit in limits {
+        let exceeds = |f: &miniscript::Miniscript<_, _>| matches!((limit.measure)(f), Some(n) if n > limit.max);
+        if let Some(n) = (limit.measure)(ms).filter(|n| *n > limit.max) {
+            findings.push(Finding::new(
+                limit.kind,
+                format!(
+                    "{} of {} is above the {} limit of {}",
+                    limit.name, n, limit.kind, limit.max
+                ),
+                innermost_fragment(ms, &exceeds),
+            ));
+        }
+    }
+    findings
+}
+
+/// A resource limit of a script context, measured for every fragment to find the innermost one
+/// that exceeds it
+pub struct Limit<Ctx: ScriptContext> {
+    kind: CheckKind,
+    name: &'static str,
+    max: usize,
+    measure: fn(&miniscript::Miniscript<DescriptorPublicKey, Ctx>) -> Option<usize>,
+}
+
+fn ops_limit<Ctx: ScriptContext>() -> Limit<Ctx> {
+    Limit {
+        kind: CheckKind::Consensus,
+        name: "Op count",
+        max: MAX_OPS_PER_SCRIPT,
+        measure: |ms| ms.ext.ops_count_sat,
+    }
+}
+
+fn script_size_limit<Ctx: ScriptContext>(
+    kind: CheckKind,
+    name: &'static str,
+    max: usize,
+) -> Limit<Ctx> {
+    Limit {
+        kind,
+        name,
+        max,
+        measure: |ms| Some(ms.ext.pk_cost),
+    }
+}
+
+pub fn segwit_limits() -> Vec<Limit<Segwitv0>> {
+    vec![
+        ops_limit(),
+        script_size_limit(CheckKind::Consensus, "Witness script size", MAX_SCRIPT_SIZE),
+        script_size_limit(
+            CheckKind::Standardness,
+            "Witness script size",
+            MAX_STANDARD_P2WSH_SCRIPT_SIZE,
+        ),
+        Limit {
+            kind: CheckKind::Standardness,
+            name: "Witness elements count",
+            max: MAX_STANDARD_P2WSH_STACK_ITEMS,
+            measure: |ms| ms.max_satisfaction_witness_elements().ok(),
+        },
+    ]
+}
+
+pub fn legacy_limits() -> Vec<Limit<Legacy>> {
+    vec![
+        ops_limit(),
+        script_size_limit(
+            CheckKind::Consensus,
+            "Redeem script size",
+            MAX_SCRIPT_ELEMENT_SIZE,
+        ),
+        Limit {
+            kind: CheckKind::Standardness,
+            name: "Script sig size",
+            max: MAX_SCRIPTSIG_SIZE,
+            measure: |ms| ms.max_satisfaction_size().ok(),
+        },
+    ]
+}
+
+pub fn bare_limits() -> Vec<Limit<BareCtx>> {
+    vec![
+        ops_limit(),
+        script_size_limit(CheckKind::Consensus, "Script size", MAX_SCRIPT_SIZE),
+    ]
+}
+
+// Tapscript has no op count or script size limits, only the limit on the stack size
+pub fn tap_limits() -> Vec<Limit<Tap>> {
+    vec![Limit {
+        kind: CheckKind::Consensus,
+        name: "Witness elements count",
+        max: MAX_STACK_SIZE,
+        measure: |ms| ms.max_satisfaction_witness_elements().ok(),
+    }]
+}
+
+// Check the structure of every sub-policy, for things the miniscript compiler rejects or that
+// make a branch unsatisfiable
+fn check_policy_nodes(policy: &Policy, findings: &mut Vec<Finding>) {
+    let problem = match policy {
+        Policy::And(subs) if subs.len() != 2 => Some(format!(
+            "and() with {} sub-policies, use all() for more than two",
+            subs.len()
+        )),
+        Policy::Or(subs) if subs.len() != 2 => Some(format!(
+            "or() with {} sub-policies, use any() for more than two",
+            subs.len()
+        )),
+        Policy::Threshold(k, subs) if *k == 0 || *k > subs.len() => Some(format!(
+            "Threshold of {} must be between 1 and the number of sub-policies ({})",
+            k,
+            subs.len()
+        )),
+        // Report the same errors as older() and after() do
+        Policy::Older(n) => number_to_seq(*n as usize).err().map(|e| e.to_string()),
+        Policy::After(n) => match number_to_locktime(*n as usize) {
+            Err(e) => Some(e.to_string()),
+            Ok(n) if n > 1 << 31 => Some(format!(
+                "Timelock of {} is too far in the future (must be up to 2^31)",
+                n
+            )),
+            Ok(_) => None,
+        },
+        _ => None,
+    };
+    if let Some(message) = problem {
+        findings.push(Finding::new(CheckKind::Sanity, message, policy));
+    }
+    for sub in sub_policies(policy) {
+        check_policy_nodes(sub, findings);
+    }
+}
+
+fn check_keys(policy: &Policy) -> Vec<Finding> {
+    let mut keys = policy.keys();
+    keys.sort();
+    let mut duplicates: Vec<_> = keys
+        .windows(2)
+        .filter(|w| w[0] == w[1])
+        .map(|w| w[0])
+        .collect();
+    duplicates.dedup();
+    duplicates
+        .into_iter()
+        .map(|pk| {
+            Finding::new(
+                CheckKind::Sanity,
+                "Key is used more than once, use a separate key for every condition",
+                Policy::Key(pk.clone()),
+            )
+        })
+        .collect()
+}
+
+// Report every spending path that does not require a signature. These are the paths that remain
+// once the key conditions are pruned away, or the pruned policy itself if there are too many of them.
+fn check_sigless(policy: &Policy) -> Vec<Finding> {
+    let message = "Spending path does not require a signature, anyone who learns it can spend";
+    let sigless = prune(policy, &|c| !matches!(c, Policy::Key(_)));
+    let paths = match spending_paths(&sigless) {
+        Ok(paths) => paths,
+        Err(_) => return vec![Finding::new(CheckKind::Sanity, message, sigless)],
+    };
+    paths
+        .into_iter()
+        .map(|path| {
+            let conditions: Vec<_> = path.iter().map(Policy::to_string).collect();
+            let culprit = match conditions.is_empty() {
+                true => "true".to_string(),
+                false => conditions.join(" && "),
+            };
+            Finding::new(CheckKind::Sanity, message, culprit)
+        })
+        .collect()
+}
+
+fn mixed_timelocks(culprit: impl fmt::Display) -> Finding {
+    Finding::new(
+        CheckKind::Sanity,
+        "Height-based and time-based timelocks are combined in the same spending path, making it unsatisfiable",
+        culprit,
+    )
+}
+
+fn sub_policies(policy: &Policy) -> Vec<&Policy> {
+    match policy {
+        Policy::And(subs) | Policy::Threshold(_, subs) => subs.iter().collect(),
+        Policy::Or(subs) => subs.iter().map(|(_, sub)| sub).collect(),
+        _ => vec![],
+    }
+}
+
+// Find the innermost sub-policy that still fails the check
+fn innermost_policy<'a>(policy: &'a Policy, fails: &dyn Fn(&Policy) -> bool) -> &'a Policy {
+    match sub_policies(policy).into_iter().find(|sub| fails(sub)) {
+        Some(sub) => innermost_policy(sub, fails),
+        None => policy,
+    }
+}
+
+fn innermost_fragment<'a, Ctx: ScriptContext>(
+    ms: &'a miniscript::Miniscript<DescriptorPublicKey, Ctx>,
+    fails: &dyn Fn(&miniscript::Miniscript<DescriptorPublicKey, Ctx>) -> bool,
+) -> &'a miniscript::Miniscript<DescriptorPublicKey, Ctx> {
+    match ms.branches().into_iter().find(|sub| fails(sub)) {
+        Some(sub) => innermost_fragment(sub, fails),
+        None => ms,
+    }
+}
@@ -1,6 +1,13 @@
 /// Expressions have no side-effects and produce a value
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+/// The expression types, without their source location
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     Block(Block),
     Call(Call),
     Or(Or),
@@ -11,22 +18,123 @@ pub enum Expr {
     Array(Array),
     ArrayAccess(ArrayAccess),
     ChildDerive(ChildDerive),
+    FnExpr(FnExpr),
+    Infix(Infix),
+    Not(Not),
+    If(If),
+    Interpolation(Interpolation),
+    Wrap(Wrap),
 
     // Atoms
     PubKey(String),
     Hash(String),
     Number(usize),
+    Bool(bool),
+    String(String),
     Duration(Duration),
     DateTime(String),
 }
 
-impl_from_variant!(usize, Expr, Number);
+impl_from_variant!(usize, ExprKind, Number);
+
+/// The location of a node in the source code, as byte offsets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    /// The source code the offsets refer to, 0 for the main program or the id of an imported module
+    pub source: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span {
+            start,
+            end,
+            source: 0,
+        }
+    }
+
+    /// Get the 1-based line and column numbers of the span start within `code`
+    pub fn line_col(&self, code: &str) -> (usize, usize) {
+        let before = &code[..floor_char_boundary(code, self.start)];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().unwrap().chars().count() + 1;
+        (line, col)
+    }
+}
+
+impl Expr {
+    /// Apply `f` to the span of this expression and to the spans of the expressions and statements
+    /// nested within it
+    pub fn for_each_span<F: FnMut(&mut Span)>(&mut self, f: &mut F) {
+        f(&mut self.span);
+        match &mut self.kind {
+            ExprKind::Block(block) => block.for_each_span(f),
+            ExprKind::Call(Call { args: exprs, .. })
+            | ExprKind::Or(Or(exprs))
+            | ExprKind::And(And(exprs))
+            | ExprKind::Array(Array(exprs))
+            | ExprKind::Interpolation(Interpolation(exprs)) => {
+                for expr in exprs {
+                    expr.for_each_span(f);
+                }
+            }
+            ExprKind::Thresh(Thresh {
+                thresh: a,
+                policies: b,
+            })
+            | ExprKind::WithProb(WithProb { prob: a, expr: b })
+            | ExprKind::ArrayAccess(ArrayAccess { array: a, index: b })
+            | ExprKind::Infix(Infix { lhs: a, rhs: b, .. }) => {
+                a.for_each_span(f);
+                b.for_each_span(f);
+            }
+            ExprKind::ChildDerive(ChildDerive { parent, path, .. }) => {
+                parent.for_each_span(f);
+                for expr in path {
+                    expr.for_each_span(f);
+                }
+            }
+            ExprKind::FnExpr(FnExpr { body: expr, .. })
+            | ExprKind::Not(Not(expr))
+            | ExprKind::Wrap(Wrap { expr, .. }) => expr.for_each_span(f),
+            ExprKind::If(If {
+                condition,
+                then_val,
+                else_val,
+            }) => {
+                condition.for_each_span(f);
+                then_val.for_each_span(f);
+                else_val.for_each_span(f);
+            }
+            ExprKind::Ident(_)
+            | ExprKind::PubKey(_)
+            | ExprKind::Hash(_)
+            | ExprKind::Number(_)
+            | ExprKind::Bool(_)
+            | ExprKind::String(_)
+            | ExprKind::Duration(_)
+            | ExprKind::DateTime(_) => (),
+        }
+    }
+}
+
+/// Get the closest char boundary at or before `index`, clamped to the length of `code`
+pub(crate) fn floor_char_boundary(code: &str, index: usize) -> usize {
+    let mut index = index.min(code.len());
+    while !code.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
 
 /// Statements have side-effects and don't produce a value
 #[derive(Debug, Clone)]
 pub enum Stmt {
     FnDef(FnDef),
     Assign(Assign),
+    Import(Import),
 }
 
 /// A collection of statements and a final expression used as the return value.
@@ -36,7 +144,30 @@ pub struct Block {
     pub stmts: Vec<Stmt>,
     pub return_value: Option<Box<Expr>>,
 }
-impl_from_variant!(Block, Expr);
+impl_from_variant!(Block, ExprKind);
+
+impl Block {
+    fn for_each_span<F: FnMut(&mut Span)>(&mut self, f: &mut F) {
+        for stmt in &mut self.stmts {
+            match stmt {
+                Stmt::FnDef(fn_def) => {
+                    f(&mut fn_def.span);
+                    fn_def.body.for_each_span(f);
+                }
+                Stmt::Assign(Assign(assignments)) => {
+                    for assignment in assignments {
+                        f(&mut assignment.span);
+                        assignment.rhs.for_each_span(f);
+                    }
+                }
+                Stmt::Import(import) => f(&mut import.span),
+            }
+        }
+        if let Some(return_value) = &mut self.return_value {
+            return_value.for_each_span(f);
+        }
+    }
+}
 
 /// A function call expression
 #[derive(Debug, Clone)]
@@ -44,17 +175,17 @@ pub struct Call {
     pub ident: Ident,
     pub args: Vec<Expr>,
 }
-impl_from_variant!(Call, Expr);
+impl_from_variant!(Call, ExprKind);
 
 /// Logical OR expression
 #[derive(Debug, Clone)]
 pub struct Or(pub Vec<Expr>);
-impl_from_variant!(Or, Expr);
+impl_from_variant!(Or, ExprKind);
 
 /// Logical AND expression
 #[derive(Debug, Clone)]
 pub struct And(pub Vec<Expr>);
-impl_from_variant!(And, Expr);
+impl_from_variant!(And, ExprKind);
 
 /// Threshold expression
 #[derive(Debug, Clone)]
@@ -62,12 +193,12 @@ pub struct Thresh {
     pub thresh: Box<Expr>,
     pub policies: Box<Expr>,
 }
-impl_from_variant!(Thresh, Expr);
+impl_from_variant!(Thresh, ExprKind);
 
 /// A terminal word expression. This can either be a variable name or a plain value passed-through to miniscript.
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct Ident(pub String);
-impl_from_variant!(Ident, Expr);
+impl_from_variant!(Ident, ExprKind);
 impl From<&str> for Ident {
     fn from(s: &str) -> Self {
         Ident(s.into())
@@ -85,19 +216,19 @@ pub struct WithProb {
     pub prob: Box<Expr>,
     pub expr: Box<Expr>,
 }
-impl_from_variant!(WithProb, Expr);
+impl_from_variant!(WithProb, ExprKind);
 
 /// An array expression
 #[derive(Debug, Clone)]
 pub struct Array(pub Vec<Expr>);
-impl_from_variant!(Array, Expr);
+impl_from_variant!(Array, ExprKind);
 
 #[derive(Debug, Clone)]
 pub struct ArrayAccess {
     pub array: Box<Expr>,
     pub index: Box<Expr>,
 }
-impl_from_variant!(ArrayAccess, Expr);
+impl_from_variant!(ArrayAccess, ExprKind);
 
 #[derive(Debug, Clone)]
 pub struct ChildDerive {
@@ -105,7 +236,70 @@ pub struct ChildDerive {
     pub path: Vec<Expr>,
     pub is_wildcard: bool,
 }
-impl_from_variant!(ChildDerive, Expr);
+impl_from_variant!(ChildDerive, ExprKind);
+
+/// An anonymous function expression
+#[derive(Debug, Clone)]
+pub struct FnExpr {
+    pub signature: Vec<Ident>,
+    pub body: Box<Expr>,
+}
+impl_from_variant!(FnExpr, ExprKind);
+
+/// A binary operator expression
+#[derive(Debug, Clone)]
+pub struct Infix {
+    pub op: InfixOp,
+    pub lhs: Box<Expr>,
+    pub rhs: Box<Expr>,
+}
+impl_from_variant!(Infix, ExprKind);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfixOp {
+    // Boolean operators
+    And,
+    Or,
+    // Arithmetic operators (division is handled by ChildDerive)
+    Add,
+    Sub,
+    Mul,
+    Mod,
+    // Comparison operators
+    Eq,
+    NotEq,
+    Lt,
+    Gt,
+    Lte,
+    Gte,
+}
+
+/// Boolean negation expression
+#[derive(Debug, Clone)]
+pub struct Not(pub Box<Expr>);
+impl_from_variant!(Not, ExprKind);
+
+/// Conditional expression
+#[derive(Debug, Clone)]
+pub struct If {
+    pub condition: Box<Expr>,
+    pub then_val: Box<Expr>,
+    pub else_val: Box<Expr>,
+}
+impl_from_variant!(If, ExprKind);
+
+/// String interpolation expression, with the literal parts represented as String expressions
+#[derive(Debug, Clone)]
+pub struct Interpolation(pub Vec<Expr>);
+impl_from_variant!(Interpolation, ExprKind);
+
+/// Miniscript wrapper expression, like `v:pk(A)`. The wrappers are applied right-to-left.
+#[derive(Debug, Clone)]
+pub struct Wrap {
+    pub wrappers: String,
+    pub expr: Box<Expr>,
+}
+impl_from_variant!(Wrap, ExprKind);
 
 // Duration (relative block height or time)
 #[derive(Debug, Clone)]
@@ -116,7 +310,7 @@ pub enum Duration {
         heightwise: bool,
     },
 }
-impl_from_variant!(Duration, Expr);
+impl_from_variant!(Duration, ExprKind);
 
 #[derive(Debug, Clone)]
 pub enum DurationPart {
@@ -135,6 +329,7 @@ pub struct FnDef {
     pub ident: Ident,
     pub signature: Vec<Ident>,
     pub body: Expr,
+    pub span: Span,
 }
 impl_from_variant!(FnDef, Stmt);
 
@@ -147,4 +342,15 @@ impl_from_variant!(Assign, Stmt);
 pub struct Assignment {
     pub lhs: Ident,
     pub rhs: Expr,
+    pub span: Span,
+}
+
+/// An import statement, binding the definitions of another module into the current scope
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub path: String,
+    /// Namespace to prefix the imported names with, as `alias.name`
+    pub alias: Option<Ident>,
+    pub span: Span,
 }
+impl_from_variant!(Import, Stmt);
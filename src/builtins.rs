@@ -1,18 +1,23 @@
 use std::convert::TryInto;
+use std::sync::Arc;
 
 use miniscript::bitcoin::{Address, Network};
+use miniscript::descriptor::{DescriptorPublicKey, SortedMultiVec, TapTree};
+use miniscript::{BareCtx, DescriptorTrait, Legacy, ScriptContext, Segwitv0, Tap, Terminal};
 
-use crate::function::{Function, NativeFunction};
-use crate::runtime::Value;
-use crate::time::{duration_to_seq, parse_datetime};
-use crate::util::get_descriptor_ctx;
-use crate::{Descriptor, Policy, Result, Scope};
+use crate::analyze;
+use crate::checksum::{desc_checksum, strip_checksum};
+use crate::function::{Call, Function};
+use crate::runtime::{Array, Value};
+use crate::taproot;
+use crate::time::{duration_to_seq, number_to_locktime, number_to_seq, parse_datetime};
+use crate::util::derive_descriptor;
+use crate::{Descriptor, Error, Miniscript, Policy, Result, Scope};
 
 /// Attach built-in functions to the Minsc runtime envirnoment
-pub fn attach_builtins(scope: &mut Scope) {
-    let mut attach = |ident, body| {
-        let func = Function::from(NativeFunction { body });
-        scope.set(ident, func.into()).unwrap();
+pub fn attach_builtins(scope: &Scope) {
+    let attach = |ident, body: fn(Vec<Value>, &Scope) -> Result<Value>| {
+        scope.set_fn(ident, body).unwrap();
     };
 
     // Miniscript Policy functions exposed in the Minsc runtime
@@ -27,10 +32,31 @@ pub fn attach_builtins(scope: &mut Scope) {
     attach("ripemd160", fns::ripemd160);
     attach("hash160", fns::hash160);
 
+    // Multisig functions
+    attach("multi", fns::multi);
+    attach("sortedmulti", fns::sortedmulti);
+
+    // Miniscript fragment functions
+    attach("pk_k", fns::pk_k);
+    attach("pk_h", fns::pk_h);
+    attach("and_v", fns::and_v);
+    attach("and_b", fns::and_b);
+    attach("and_n", fns::and_n);
+    attach("andor", fns::andor);
+    attach("or_b", fns::or_b);
+    attach("or_c", fns::or_c);
+    attach("or_d", fns::or_d);
+    attach("or_i", fns::or_i);
+
     // Descriptor functions
     attach("wsh", fns::wsh);
     attach("wpkh", fns::wpkh);
     attach("sh", fns::sh);
+    attach("pkh", fns::pkh);
+    attach("bare", fns::bare);
+    attach("tr", fns::tr);
+    attach("taproot", fns::taproot);
+    attach("taproot_tree", fns::taproot_tree);
 
     // Minsc policy functions
     attach("prob", fns::prob);
@@ -38,8 +64,33 @@ pub fn attach_builtins(scope: &mut Scope) {
     attach("all", fns::all);
     attach("any", fns::any);
 
+    // Policy analysis
+    attach("spending_paths", fns::spending_paths);
+    attach("cost", fns::cost);
+    attach("check", fns::check);
+    attach("lift", fns::lift);
+    attach("equivalent", fns::equivalent);
+    attach("at_age", fns::at_age);
+    attach("at_time", fns::at_time);
+    attach("can_spend", fns::can_spend);
+    attach("keys", fns::keys);
+    attach("hashes", fns::hashes);
+    attach("timelocks", fns::timelocks);
+
+    // Array functions
+    attach("map", fns::map);
+    attach("filter", fns::filter);
+    attach("flat_map", fns::flat_map);
+    attach("reduce", fns::reduce);
+    attach("fold", fns::fold);
+    attach("zip", fns::zip);
+
     // Compile policy to miniscript
     attach("miniscript", fns::miniscript);
+    // Parse policies and descriptors from strings
+    attach("policy", fns::policy);
+    attach("descriptor", fns::descriptor);
+    attach("checksum", fns::checksum);
     // Script functions
     attach("script_pubkey", fns::script_pubkey);
     attach("script_witness", fns::script_witness);
@@ -59,10 +110,9 @@ pub fn attach_builtins(scope: &mut Scope) {
 
 pub mod fns {
     use super::*;
-    use crate::Error;
     const LIKELY_PROB: usize = 10;
 
-    pub fn or(args: Vec<Value>) -> Result<Value> {
+    pub fn or(args: Vec<Value>, _: &Scope) -> Result<Value> {
         let policies_with_probs = args
             .into_iter()
             .map(|arg| match arg {
@@ -73,152 +123,706 @@ pub mod fns {
         Ok(Policy::Or(policies_with_probs).into())
     }
 
-    pub fn and(args: Vec<Value>) -> Result<Value> {
+    pub fn and(args: Vec<Value>, _: &Scope) -> Result<Value> {
         let policies = map_policy(args)?;
         Ok(Policy::And(policies).into())
     }
 
-    pub fn thresh(mut args: Vec<Value>) -> Result<Value> {
+    // Produces a Miniscript thresh() if any of the subs is a Miniscript, or a Policy threshold otherwise
+    pub fn thresh(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(!args.is_empty(), Error::InvalidArguments);
         let thresh_n = args.remove(0).into_usize()?;
         // Support thresh(n, $array) as well as thresh(n, pol1, pol2, ...) invocations
-        let policies = if args.len() == 1 && args[0].is_array() {
-            map_policy_array(args.remove(0))?
+        let subs = if args.len() == 1 && args[0].is_array() {
+            args.remove(0).into_array_elements()?
         } else {
-            map_policy(args)?
+            args
         };
-        Ok(Policy::Threshold(thresh_n, policies).into())
+        if subs.iter().any(|sub| matches!(sub, Value::Miniscript(_))) {
+            let subs = subs.into_iter().map(into_fragment).collect::<Result<_>>()?;
+            fragment(Terminal::Thresh(thresh_n, subs))
+        } else {
+            Ok(Policy::Threshold(thresh_n, map_policy(subs)?).into())
+        }
     }
 
-    pub fn older(mut args: Vec<Value>) -> Result<Value> {
+    pub fn older(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
         let locktime = match args.remove(0) {
             Value::Duration(dur) => duration_to_seq(&dur)?,
-            Value::Number(num) => num as u32,
+            Value::Number(num) => number_to_seq(num)?,
             _ => bail!(Error::InvalidArguments),
         };
         Ok(Policy::Older(locktime).into())
     }
 
-    pub fn after(mut args: Vec<Value>) -> Result<Value> {
+    pub fn after(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
         let locktime = match args.remove(0) {
             Value::DateTime(datetime) => parse_datetime(&datetime)?,
-            Value::Number(num) => num as u32,
+            Value::Number(num) => number_to_locktime(num)?,
             _ => bail!(Error::InvalidArguments),
         };
         Ok(Policy::After(locktime).into())
     }
 
-    pub fn pk(mut args: Vec<Value>) -> Result<Value> {
+    pub fn pk(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
         Ok(Policy::Key(args.remove(0).into_key()?).into())
     }
 
-    pub fn sha256(mut args: Vec<Value>) -> Result<Value> {
+    pub fn sha256(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
         Ok(Policy::Sha256(args.remove(0).try_into()?).into())
     }
-    pub fn hash256(mut args: Vec<Value>) -> Result<Value> {
+    pub fn hash256(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
         Ok(Policy::Hash256(args.remove(0).try_into()?).into())
     }
 
-    pub fn ripemd160(mut args: Vec<Value>) -> Result<Value> {
+    pub fn ripemd160(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
         Ok(Policy::Ripemd160(args.remove(0).try_into()?).into())
     }
-    pub fn hash160(mut args: Vec<Value>) -> Result<Value> {
+    pub fn hash160(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
         Ok(Policy::Hash160(args.remove(0).try_into()?).into())
     }
 
-    // Policy -> Miniscript
-    pub fn miniscript(mut args: Vec<Value>) -> Result<Value> {
+    // Key -> Miniscript
+    pub fn pk_k(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
-        Ok(args.remove(0).into_miniscript()?.into())
+        fragment(Terminal::PkK(args.remove(0).into_key()?))
+    }
+    pub fn pk_h(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        fragment(Terminal::PkH(args.remove(0).into_key()?))
+    }
+
+    // Miniscript, Policy or 0/1 -> Miniscript
+    pub fn and_v(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        let [x, y] = fragment_args(args)?;
+        fragment(Terminal::AndV(x, y))
+    }
+    pub fn and_b(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        let [x, y] = fragment_args(args)?;
+        fragment(Terminal::AndB(x, y))
+    }
+    // `and_n(X, Y)` -> `andor(X, Y, 0)`
+    pub fn and_n(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        let [x, y] = fragment_args(args)?;
+        fragment(Terminal::AndOr(x, y, into_fragment(Value::Number(0))?))
+    }
+    pub fn andor(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        let [x, y, z] = fragment_args(args)?;
+        fragment(Terminal::AndOr(x, y, z))
+    }
+    pub fn or_b(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        let [x, z] = fragment_args(args)?;
+        fragment(Terminal::OrB(x, z))
+    }
+    pub fn or_c(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        let [x, z] = fragment_args(args)?;
+        fragment(Terminal::OrC(x, z))
+    }
+    pub fn or_d(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        let [x, z] = fragment_args(args)?;
+        fragment(Terminal::OrD(x, z))
+    }
+    pub fn or_i(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        let [x, z] = fragment_args(args)?;
+        fragment(Terminal::OrI(x, z))
+    }
+
+    // Policy -> Miniscript, or parse a miniscript string
+    pub fn miniscript(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        Ok(match args.remove(0) {
+            Value::String(s) => s.parse::<Miniscript>()?,
+            other => other.into_miniscript()?,
+        }
+        .into())
+    }
+
+    // Parse a policy string
+    pub fn policy(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        Ok(match args.remove(0) {
+            Value::String(s) => s.parse::<Policy>()?,
+            other => other.into_policy()?,
+        }
+        .into())
+    }
+
+    // Parse a descriptor string, verifying its checksum if it has one
+    pub fn descriptor(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        Ok(match args.remove(0) {
+            Value::String(s) => strip_checksum(&s)?.parse::<Descriptor>()?,
+            other => other.into_desc()?,
+        }
+        .into())
+    }
+
+    // Descriptor (or a descriptor string without the '#' suffix) -> BIP380 checksum string
+    pub fn checksum(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        let checksum = match args.remove(0) {
+            Value::String(s) => desc_checksum(&s)?,
+            other => {
+                // Descriptors are displayed with their checksum already attached
+                let desc = other.into_desc()?.to_string();
+                desc_checksum(strip_checksum(&desc)?)?
+            }
+        };
+        Ok(checksum.into())
     }
 
     // Key -> Descriptor::Wpkh
-    pub fn wpkh(mut args: Vec<Value>) -> Result<Value> {
+    pub fn wpkh(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
-        Ok(Descriptor::Wpkh(args.remove(0).into_key()?).into())
+        Ok(Descriptor::new_wpkh(args.remove(0).into_key()?)?.into())
     }
 
-    // Policy or Miniscript -> Descriptor::Wsh
-    pub fn wsh(mut args: Vec<Value>) -> Result<Value> {
+    // Policy, Miniscript or SortedMulti -> Descriptor::Wsh{,SortedMulti}
+    pub fn wsh(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
-        Ok(Descriptor::Wsh(args.remove(0).into_miniscript()?).into())
+        Ok(match args.remove(0) {
+            Value::SortedMulti(smv) => Descriptor::new_wsh_sortedmulti(smv.k, smv.pks)?,
+            other => {
+                let miniscript = other.into_miniscript()?;
+                check_top_level(&miniscript)?;
+                Descriptor::new_wsh(miniscript)?
+            }
+        }
+        .into())
     }
 
-    // Descriptor::W{sh,pkh} -> Descriptor::ShW{sh,pkh}
-    pub fn sh(mut args: Vec<Value>) -> Result<Value> {
+    // Descriptor::W{sh,pkh} -> Descriptor::Sh wrapping the segwit descriptor (p2sh-nested segwit)
+    // Policy, Miniscript or SortedMulti -> Descriptor::Sh{,SortedMulti} (legacy p2sh)
+    pub fn sh(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
         Ok(match args.remove(0) {
             Value::Descriptor(desc) => match desc {
-                Descriptor::Wsh(miniscript) => Descriptor::ShWsh(miniscript),
-                Descriptor::Wpkh(key) => Descriptor::ShWpkh(key),
+                Descriptor::Wsh(wsh) => Descriptor::new_sh_with_wsh(wsh),
+                Descriptor::Wpkh(wpkh) => Descriptor::new_sh_with_wpkh(wpkh),
                 _ => bail!(Error::InvalidShUse),
             },
+            Value::Policy(policy) => Descriptor::new_sh(compile_ctx::<Legacy>(&policy)?)?,
+            Value::Miniscript(miniscript) => Descriptor::new_sh(into_ctx(miniscript)?)?,
+            Value::SortedMulti(smv) => Descriptor::new_sh_sortedmulti(smv.k, smv.pks)?,
             _ => bail!(Error::InvalidShUse),
         }
         .into())
     }
 
+    // Key -> Descriptor::Pkh
+    pub fn pkh(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        Ok(Descriptor::new_pkh(args.remove(0).into_key()?).into())
+    }
+
+    // Key -> Descriptor::Pk, Policy or Miniscript -> Descriptor::Bare
+    pub fn bare(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        Ok(match args.remove(0) {
+            Value::PubKey(key) => Descriptor::new_pk(key),
+            Value::Policy(policy) => Descriptor::new_bare(compile_ctx::<BareCtx>(&policy)?)?,
+            Value::Miniscript(miniscript) => Descriptor::new_bare(into_ctx(miniscript)?)?,
+            v => bail!(Error::NotMiniscriptLike(v)),
+        }
+        .into())
+    }
+
+    // `tr(internal_key)` or `tr(internal_key, tree)` -> Descriptor::Tr
+    // The tree is a Policy or Miniscript leaf, or an array of two sub-trees
+    pub fn tr(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1 || args.len() == 2, Error::InvalidArguments);
+        let tree = match args.len() {
+            2 => Some(into_tap_tree(args.pop().unwrap())?),
+            _ => None,
+        };
+        let internal_key = args.remove(0).into_key()?;
+        Ok(Descriptor::new_tr(internal_key, tree)?.into())
+    }
+
+    // Policy -> Descriptor::Tr, with the most likely key-only branch as the internal key and the other
+    // branches compiled into a Huffman tree of tapscript leaves
+    pub fn taproot(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        let (internal_key, tree) = taproot::split(&args.remove(0).into_policy()?);
+        let tree = tree.map(|tree| into_tap_tree(tree.into())).transpose()?;
+        Ok(Descriptor::new_tr(internal_key, tree)?.into())
+    }
+
+    // Policy -> [ ["internal_key", key], ["tree", tree] ], with the uncompiled policy leaves of the tree
+    // used by taproot() given as nested arrays of two sub-trees (or false when there are none)
+    pub fn taproot_tree(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        let (internal_key, tree) = taproot::split(&args.remove(0).into_policy()?);
+        let pair = |name: &str, value: Value| Array(vec![name.to_string().into(), value]).into();
+        Ok(Array(vec![
+            pair("internal_key", internal_key.into()),
+            pair("tree", tree.map_or(false.into(), Value::from)),
+        ])
+        .into())
+    }
+
+    // `multi(k, $keys)` or `multi(k, key1, key2, ...)` -> Miniscript
+    pub fn multi(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        let (k, pks) = multi_args(args)?;
+        let miniscript = Miniscript::from_ast(Terminal::Multi(k, pks))?;
+        Segwitv0::check_local_validity(&miniscript).map_err(miniscript::Error::from)?;
+        Ok(miniscript.into())
+    }
+
+    // `sortedmulti(k, $keys)` or `sortedmulti(k, key1, key2, ...)` -> SortedMulti
+    pub fn sortedmulti(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        let (k, pks) = multi_args(args)?;
+        Ok(SortedMultiVec::new(k, pks)?.into())
+    }
+
     // Descriptor, Policy, Miniscript, or Key -> Pubkey Script
-    pub fn script_pubkey(mut args: Vec<Value>) -> Result<Value> {
+    pub fn script_pubkey(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
-        let ctx = get_descriptor_ctx(0);
         let descriptor = args.remove(0).into_desc()?;
-        Ok(descriptor.script_pubkey(ctx).into())
+        Ok(derive_descriptor(&descriptor, 0)?.script_pubkey().into())
     }
 
     // Descriptor, Policy, Miniscript, or Key -> Witness Script
-    pub fn script_witness(mut args: Vec<Value>) -> Result<Value> {
+    pub fn script_witness(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
-        let ctx = get_descriptor_ctx(0);
         let descriptor = args.remove(0).into_desc()?;
-        Ok(descriptor.witness_script(ctx).into())
+        Ok(derive_descriptor(&descriptor, 0)?.explicit_script()?.into())
     }
 
     // Descriptor, Policy, Miniscript, Script or Key -> Address
-    pub fn address(mut args: Vec<Value>) -> Result<Value> {
+    pub fn address(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1 || args.len() == 2, Error::InvalidArguments);
         let script = args.remove(0).into_script_pubkey()?;
         let network = args.pop().map_or(Ok(Network::Testnet), TryInto::try_into)?;
-        let address = Address::from_script(&script, network)
-            .expect("non-addressable descriptors cannot be constructed");
+        let address = Address::from_script(&script, network).ok_or(Error::NotAddressable)?;
         Ok(address.into())
     }
 
     // `prob(A, B)` -> `A@B`
-    pub fn prob(mut args: Vec<Value>) -> Result<Value> {
+    pub fn prob(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 2, Error::InvalidArguments);
         let prob_n = match args.remove(0) {
-            #[allow(clippy::fn_address_comparisons)] // should be safe in this case
             // support the `likely@X` syntax as an alternative to the `likely(X)` function invocation
-            Value::Function(Function::Native(f)) if f.body == fns::likely => LIKELY_PROB,
+            Value::Function(Function::Native(f)) if f.ident.0 == "likely" => LIKELY_PROB,
             v => v.into_usize()?,
         };
         let policy = args.remove(0).into_policy()?;
         Ok(Value::WithProb(prob_n, policy))
     }
 
-    pub fn likely(mut args: Vec<Value>) -> Result<Value> {
+    pub fn likely(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
         Ok(Value::WithProb(LIKELY_PROB, args.remove(0).into_policy()?))
     }
 
-    pub fn all(mut args: Vec<Value>) -> Result<Value> {
+    pub fn all(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
         let policies = map_policy_array(args.remove(0))?;
         Ok(Policy::Threshold(policies.len(), policies).into())
     }
 
-    pub fn any(mut args: Vec<Value>) -> Result<Value> {
+    pub fn any(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
         ensure!(args.len() == 1, Error::InvalidArguments);
         let policies = map_policy_array(args.remove(0))?;
         Ok(Policy::Threshold(1, policies).into())
     }
+
+    // Policy -> array of the minimal sets of conditions that satisfy it
+    pub fn spending_paths(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        let policy = args.remove(0).into_policy()?;
+        let paths = analyze::spending_paths(&policy)?
+            .into_iter()
+            .map(|path| Array(path.into_iter().map(Value::from).collect()).into())
+            .collect();
+        Ok(Array(paths).into())
+    }
+
+    // Descriptor -> array of [name, value] pairs with its script size, op count and weights
+    pub fn cost(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        let cost = analyze::cost(&args.remove(0).into_desc()?, analyze::MAX_WEIGHED_PATHS)?;
+        // Weights that cannot be computed (for unsatisfiable scripts or paths) are returned as false
+        let weight = |w: Option<usize>| w.map_or(false.into(), Value::from);
+        let pair = |name: &str, value: Value| Array(vec![name.to_string().into(), value]).into();
+        let path_weight = |path: analyze::SpendingPath, w| {
+            let path = Array(path.into_iter().map(Value::from).collect()).into();
+            Array(vec![path, weight(w)]).into()
+        };
+        let paths = match cost.paths {
+            analyze::PathWeights::Paths(paths) => Array(
+                paths
+                    .into_iter()
+                    .map(|(path, w)| path_weight(path, w))
+                    .collect(),
+            )
+            .into(),
+            // A single path with the k-of-n threshold, as every combination has the same weight
+            analyze::PathWeights::Multi(k, pks, w) => {
+                let threshold = Policy::Threshold(k, pks.into_iter().map(Policy::Key).collect());
+                Array(vec![path_weight(vec![threshold], w)]).into()
+            }
+            analyze::PathWeights::TooMany => false.into(),
+        };
+        Ok(Array(vec![
+            pair("script_size", cost.script_size.into()),
+            pair("op_count", weight(cost.op_count)),
+            pair("max_weight", weight(cost.max_weight)),
+            pair("paths", paths),
+        ])
+        .into())
+    }
+
+    // Policy, Miniscript or Descriptor -> array of [kind, message, culprit] findings
+    pub fn check(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        let findings = analyze::check(args.remove(0))?
+            .into_iter()
+            .map(|finding| {
+                Array(vec![
+                    finding.kind.to_string().into(),
+                    finding.message.into(),
+                    finding.culprit.into(),
+                ])
+                .into()
+            })
+            .collect();
+        Ok(Array(findings).into())
+    }
+
+    // Policy, Miniscript or Descriptor -> semantic Policy
+    pub fn lift(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        Ok(analyze::lift(args.remove(0))?.into())
+    }
+
+    // Policy, Miniscript or Descriptor (x2) -> whether both have identical satisfying sets
+    pub fn equivalent(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 2, Error::InvalidArguments);
+        let b = analyze::lift(args.pop().unwrap())?;
+        let a = analyze::lift(args.pop().unwrap())?;
+        Ok(analyze::counterexample(&a, &b)?.is_none().into())
+    }
+
+    // Policy and Duration or BIP68 sequence number -> Policy with the branches satisfiable at that age
+    pub fn at_age(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 2, Error::InvalidArguments);
+        let age = match args.pop().unwrap() {
+            Value::Duration(dur) => duration_to_seq(&dur)?,
+            Value::Number(num) => number_to_seq(num)?,
+            _ => bail!(Error::InvalidArguments),
+        };
+        let policy = into_lifted_policy(args.pop().unwrap())?;
+        Ok(analyze::at_age(&policy, age).into())
+    }
+
+    // Policy and DateTime or block height -> Policy with the branches satisfiable at that time
+    pub fn at_time(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 2, Error::InvalidArguments);
+        let locktime = match args.pop().unwrap() {
+            Value::DateTime(datetime) => parse_datetime(&datetime)?,
+            Value::Number(num) => number_to_locktime(num)?,
+            _ => bail!(Error::InvalidArguments),
+        };
+        let policy = into_lifted_policy(args.pop().unwrap())?;
+        Ok(analyze::at_time(&policy, locktime).into())
+    }
+
+    // Policy, Miniscript or Descriptor and [name, value] pairs of resources -> array of [name, value]
+    // pairs with the cheapest satisfiable path, or the missing conditions
+    pub fn can_spend(mut args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 2, Error::InvalidArguments);
+        let resources = into_resources(args.pop().unwrap())?;
+        let spendability = analyze::can_spend(args.pop().unwrap(), &resources)?;
+        let pair = |name: &str, value: Value| Array(vec![name.to_string().into(), value]).into();
+        let path_value =
+            |path: analyze::SpendingPath| Array(path.into_iter().map(Value::from).collect()).into();
+        Ok(Array(match spendability {
+            analyze::Spendability::Spendable(path, weight) => vec![
+                pair("spendable", true.into()),
+                pair("path", path_value(path)),
+                pair("weight", weight.map_or(false.into(), Value::from)),
+            ],
+            analyze::Spendability::Missing(missing) => vec![
+                pair("spendable", false.into()),
+                pair(
+                    "missing",
+                    Array(missing.into_iter().map(path_value).collect()).into(),
+                ),
+            ],
+        })
+        .into())
+    }
+
+    // Policy, Miniscript or Descriptor -> array of the PubKeys it uses
+    pub fn keys(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        let keys = args[0].keys()?;
+        Ok(Array(keys.into_iter().map(Value::from).collect()).into())
+    }
+
+    // Policy, Miniscript or Descriptor -> array of the Hashes it uses
+    pub fn hashes(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        let hashes = args[0].hashes()?;
+        Ok(Array(hashes.into_iter().map(Value::Hash).collect()).into())
+    }
+
+    // Policy, Miniscript or Descriptor -> array of the older()/after() Policies it uses
+    pub fn timelocks(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(args.len() == 1, Error::InvalidArguments);
+        let timelocks = args[0].timelocks()?;
+        Ok(Array(timelocks.into_iter().map(Value::from).collect()).into())
+    }
+
+    // `map([A, B], $fn)` -> `[ $fn(A), $fn(B) ]`
+    pub fn map(mut args: Vec<Value>, scope: &Scope) -> Result<Value> {
+        ensure!(args.len() == 2, Error::InvalidArguments);
+        let func = args.pop().unwrap();
+        let elements = args.remove(0).into_array_elements()?;
+        let mapped = elements
+            .into_iter()
+            .map(|el| func.call(vec![el], scope))
+            .collect::<Result<_>>()?;
+        Ok(Array(mapped).into())
+    }
+
+    // Keep the array elements for which `$fn(el)` returns true
+    pub fn filter(mut args: Vec<Value>, scope: &Scope) -> Result<Value> {
+        ensure!(args.len() == 2, Error::InvalidArguments);
+        let func = args.pop().unwrap();
+        let mut filtered = vec![];
+        for el in args.remove(0).into_array_elements()? {
+            if func.call(vec![el.clone()], scope)?.into_bool()? {
+                filtered.push(el);
+            }
+        }
+        Ok(Array(filtered).into())
+    }
+
+    // Map each element into an array and concatenate the results
+    pub fn flat_map(mut args: Vec<Value>, scope: &Scope) -> Result<Value> {
+        ensure!(args.len() == 2, Error::InvalidArguments);
+        let func = args.pop().unwrap();
+        let mut flattened = vec![];
+        for el in args.remove(0).into_array_elements()? {
+            flattened.extend(func.call(vec![el], scope)?.into_array_elements()?);
+        }
+        Ok(Array(flattened).into())
+    }
+
+    // `reduce([A, B, C], $fn)` -> `$fn($fn(A, B), C)`
+    pub fn reduce(mut args: Vec<Value>, scope: &Scope) -> Result<Value> {
+        ensure!(args.len() == 2, Error::InvalidArguments);
+        let func = args.pop().unwrap();
+        let mut elements = args.remove(0).into_array_elements()?.into_iter();
+        let first = elements.next().ok_or(Error::InvalidArguments)?;
+        elements.try_fold(first, |acc, el| func.call(vec![acc, el], scope))
+    }
+
+    // `fold([A, B], $init, $fn)` -> `$fn($fn($init, A), B)`
+    pub fn fold(mut args: Vec<Value>, scope: &Scope) -> Result<Value> {
+        ensure!(args.len() == 3, Error::InvalidArguments);
+        let func = args.pop().unwrap();
+        let init = args.pop().unwrap();
+        let elements = args.remove(0).into_array_elements()?;
+        elements
+            .into_iter()
+            .try_fold(init, |acc, el| func.call(vec![acc, el], scope))
+    }
+
+    // `zip([A, B], [C, D])` -> `[ [A, C], [B, D] ]`, truncated to the shortest array
+    pub fn zip(args: Vec<Value>, _: &Scope) -> Result<Value> {
+        ensure!(!args.is_empty(), Error::InvalidArguments);
+        let arrays = args
+            .into_iter()
+            .map(Value::into_array_elements)
+            .collect::<Result<Vec<_>>>()?;
+        let len = arrays.iter().map(Vec::len).min().unwrap();
+        let mut iters: Vec<_> = arrays.into_iter().map(Vec::into_iter).collect();
+        let zipped = (0..len)
+            .map(|_| Array(iters.iter_mut().map(|it| it.next().unwrap()).collect()).into())
+            .collect();
+        Ok(Array(zipped).into())
+    }
+}
+
+/// Convert a value into a Miniscript fragment. Policy leaves map directly to their fragments, other
+/// policies get compiled. The numbers 0 and 1 are the constant `0`/`1` fragments.
+pub fn into_fragment(value: Value) -> Result<Arc<Miniscript>> {
+    let term = match value {
+        Value::Miniscript(miniscript) => return Ok(Arc::new(miniscript)),
+        Value::Number(0) => Terminal::False,
+        Value::Number(1) => Terminal::True,
+        Value::Policy(Policy::Key(pk)) => {
+            Terminal::Check(Arc::new(Miniscript::from_ast(Terminal::PkK(pk))?))
+        }
+        Value::Policy(Policy::After(n)) => Terminal::After(n),
+        Value::Policy(Policy::Older(n)) => Terminal::Older(n),
+        Value::Policy(Policy::Sha256(h)) => Terminal::Sha256(h),
+        Value::Policy(Policy::Hash256(h)) => Terminal::Hash256(h),
+        Value::Policy(Policy::Ripemd160(h)) => Terminal::Ripemd160(h),
+        Value::Policy(Policy::Hash160(h)) => Terminal::Hash160(h),
+        other => return Ok(Arc::new(other.into_miniscript()?)),
+    };
+    Ok(Arc::new(Miniscript::from_ast(term)?))
+}
+
+/// Apply Miniscript wrappers (like the `sdv` in `sdv:older(10)`) to a fragment, the rightmost first
+pub fn apply_wrappers(wrappers: &str, miniscript: Arc<Miniscript>) -> Result<Miniscript> {
+    let wrapped = wrappers
+        .chars()
+        .rev()
+        .try_fold(miniscript, |ms, wrapper| -> Result<_> {
+            let term = match wrapper {
+                'a' => Terminal::Alt(ms),
+                's' => Terminal::Swap(ms),
+                'c' => Terminal::Check(ms),
+                'd' => Terminal::DupIf(ms),
+                'v' => Terminal::Verify(ms),
+                'j' => Terminal::NonZero(ms),
+                'n' => Terminal::ZeroNotEqual(ms),
+                't' => Terminal::AndV(ms, into_fragment(Value::Number(1))?),
+                'l' => Terminal::OrI(into_fragment(Value::Number(0))?, ms),
+                'u' => Terminal::OrI(ms, into_fragment(Value::Number(0))?),
+                _ => bail!(Error::InvalidWrapper(wrapper)),
+            };
+            Ok(Arc::new(Miniscript::from_ast(term)?))
+        })?;
+    Ok(Arc::try_unwrap(wrapped).unwrap_or_else(|ms| (*ms).clone()))
+}
+
+/// Check that a Miniscript is valid for use as a top-level wsh() script
+pub fn check_top_level(miniscript: &Miniscript) -> Result<()> {
+    Segwitv0::top_level_checks(miniscript)?;
+    Segwitv0::check_local_validity(miniscript).map_err(miniscript::Error::from)?;
+    Ok(())
+}
+
+// Type-check a Miniscript fragment
+fn fragment(term: Terminal<DescriptorPublicKey, Segwitv0>) -> Result<Value> {
+    Ok(Miniscript::from_ast(term)?.into())
+}
+
+fn fragment_args<const N: usize>(args: Vec<Value>) -> Result<[Arc<Miniscript>; N]> {
+    ensure!(args.len() == N, Error::InvalidArguments);
+    let subs = args
+        .into_iter()
+        .map(into_fragment)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(subs.try_into().unwrap_or_else(|_| unreachable!()))
+}
+
+// Support thresholds with an array of keys as well as keys passed as separate arguments
+fn multi_args(mut args: Vec<Value>) -> Result<(usize, Vec<DescriptorPublicKey>)> {
+    ensure!(args.len() >= 2, Error::InvalidArguments);
+    let k = args.remove(0).into_usize()?;
+    let keys = if args.len() == 1 && args[0].is_array() {
+        args.remove(0).into_array_elements()?
+    } else {
+        args
+    };
+    let pks = keys
+        .into_iter()
+        .map(Value::into_key)
+        .collect::<Result<_>>()?;
+    Ok((k, pks))
+}
+
+// Compile a policy under the given script context, checking it against the context's limits
+fn compile_ctx<Ctx: ScriptContext>(
+    policy: &Policy,
+) -> Result<miniscript::Miniscript<DescriptorPublicKey, Ctx>> {
+    let miniscript = policy.compile::<Ctx>()?;
+    Ctx::check_local_validity(&miniscript).map_err(miniscript::Error::from)?;
+    Ctx::top_level_checks(&miniscript)?;
+    Ok(miniscript)
+}
+
+// Convert a Segwitv0 Miniscript into another script context. The encoding is identical, but the
+// contexts are subject to different resource limits and rules that get re-checked when parsing.
+fn into_ctx<Ctx: ScriptContext>(
+    miniscript: Miniscript,
+) -> Result<miniscript::Miniscript<DescriptorPublicKey, Ctx>> {
+    let converted = miniscript::Miniscript::from_str_insane(&miniscript.to_string())?;
+    Ctx::check_local_validity(&converted).map_err(miniscript::Error::from)?;
+    Ctx::top_level_checks(&converted)?;
+    Ok(converted)
+}
+
+// Build a taproot script tree from a Policy or Miniscript leaf, compiled for the Tap context, or from
+// an array of two sub-trees
+fn into_tap_tree(value: Value) -> Result<TapTree<DescriptorPublicKey>> {
+    Ok(match value {
+        Value::Array(Array(mut branches)) => {
+            ensure!(branches.len() == 2, Error::InvalidTapTree);
+            let right = into_tap_tree(branches.pop().unwrap())?;
+            let left = into_tap_tree(branches.pop().unwrap())?;
+            TapTree::Tree(Arc::new(left), Arc::new(right))
+        }
+        Value::Policy(policy) => TapTree::Leaf(Arc::new(compile_ctx::<Tap>(&policy)?)),
+        Value::Miniscript(miniscript) => TapTree::Leaf(Arc::new(into_ctx(miniscript)?)),
+        v => bail!(Error::NotMiniscriptLike(v)),
+    })
+}
+
+// Parse `[ ["keys", $keys], ["preimages", $preimages], ["age", $duration], ["time", $datetime] ]`,
+// with all of them optional
+fn into_resources(value: Value) -> Result<analyze::Resources> {
+    let mut resources = analyze::Resources::default();
+    for pair in value.into_array_elements()? {
+        let mut pair = pair.into_array_elements()?;
+        ensure!(pair.len() == 2, Error::InvalidArguments);
+        let value = pair.pop().unwrap();
+        match &*pair.pop().unwrap().into_string()? {
+            "keys" => {
+                resources.keys = value
+                    .into_array_elements()?
+                    .into_iter()
+                    .map(Value::into_key)
+                    .collect::<Result<_>>()?
+            }
+            "preimages" => {
+                resources.preimages = value
+                    .into_array_elements()?
+                    .into_iter()
+                    .map(|v| match v {
+                        Value::Hash(preimage) => Ok(preimage),
+                        v => Err(Error::NotHash(v)),
+                    })
+                    .collect::<Result<_>>()?
+            }
+            "age" => {
+                resources.age = Some(match value {
+                    Value::Duration(dur) => duration_to_seq(&dur)?,
+                    v => number_to_seq(v.into_usize()?)?,
+                })
+            }
+            "time" => {
+                resources.time = Some(match value {
+                    Value::DateTime(datetime) => parse_datetime(&datetime)?,
+                    v => number_to_locktime(v.into_usize()?)?,
+                })
+            }
+            name => bail!(Error::InvalidResource(name.into())),
+        }
+    }
+    Ok(resources)
+}
+
+// Use policies as-is, lifting miniscripts and descriptors
+fn into_lifted_policy(value: Value) -> Result<Policy> {
+    match value {
+        Value::Miniscript(_) | Value::Descriptor(_) => analyze::lift(value),
+        other => other.into_policy(),
+    }
 }
 
 fn map_policy(args: Vec<Value>) -> Result<Vec<Policy>> {
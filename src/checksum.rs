@@ -0,0 +1,86 @@
+//! Descriptor checksums, as specified in BIP 380
+
+use std::fmt::Display;
+
+use crate::error::{Error, Result};
+
+const INPUT_CHARSET: &str = "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn poly_mod(mut c: u64, val: u64) -> u64 {
+    let c0 = c >> 35;
+    c = ((c & 0x7ffffffff) << 5) ^ val;
+    if c0 & 1 != 0 {
+        c ^= 0xf5dee51989;
+    }
+    if c0 & 2 != 0 {
+        c ^= 0xa9fdca3312;
+    }
+    if c0 & 4 != 0 {
+        c ^= 0x1bab10e32d;
+    }
+    if c0 & 8 != 0 {
+        c ^= 0x3706b1677a;
+    }
+    if c0 & 16 != 0 {
+        c ^= 0x644d626ffd;
+    }
+    c
+}
+
+/// Compute the 8 character checksum of a descriptor, given without its `#` suffix
+pub fn desc_checksum(desc: &str) -> Result<String> {
+    let mut c = 1;
+    let mut cls = 0;
+    let mut clscount = 0;
+    for ch in desc.chars() {
+        let pos = INPUT_CHARSET
+            .find(ch)
+            .ok_or(Error::InvalidDescriptorChar(ch))? as u64;
+        // Emit a symbol for the position inside the group, for every character
+        c = poly_mod(c, pos & 31);
+        // Accumulate the group numbers, and emit a symbol for every 3 characters
+        cls = cls * 3 + (pos >> 5);
+        clscount += 1;
+        if clscount == 3 {
+            c = poly_mod(c, cls);
+            cls = 0;
+            clscount = 0;
+        }
+    }
+    if clscount > 0 {
+        c = poly_mod(c, cls);
+    }
+    for _ in 0..8 {
+        c = poly_mod(c, 0);
+    }
+    c ^= 1;
+
+    Ok((0..8)
+        .map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char)
+        .collect())
+}
+
+/// Strip the `#checksum` suffix off a descriptor string, verifying it if present
+pub fn strip_checksum(s: &str) -> Result<&str> {
+    match s.find('#') {
+        Some(index) => {
+            let (desc, checksum) = (&s[..index], &s[index + 1..]);
+            let expected = desc_checksum(desc)?;
+            ensure!(
+                checksum == expected,
+                Error::InvalidDescriptorChecksum(checksum.into(), expected)
+            );
+            Ok(desc)
+        }
+        None => Ok(s),
+    }
+}
+
+/// Format a descriptor with its `#checksum` suffix
+pub fn with_checksum<T: Display>(desc: &T) -> String {
+    let desc = desc.to_string();
+    // Descriptors are always encoded using characters from the checksum input charset
+    let checksum = desc_checksum(&desc).expect("valid descriptor characters");
+    format!("{}#{}", desc, checksum)
+}
@@ -2,10 +2,10 @@ use lalrpop_util::ParseError;
 use std::fmt;
 
 use miniscript::bitcoin::hashes;
-use miniscript::descriptor::DescriptorKeyParseError;
+use miniscript::descriptor::{ConversionError, DescriptorKeyParseError};
 use miniscript::policy::compiler::CompilerError;
 
-use crate::ast::Ident;
+use crate::ast::{floor_char_boundary, Ident, Span};
 use crate::runtime::Value;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -33,6 +33,9 @@ pub enum Error {
     #[error("Expected a number, not {0:?}")]
     NotNumber(Value),
 
+    #[error("Expected a boolean, not {0:?}")]
+    NotBool(Value),
+
     #[error("Expected a pubkey, not {0:?}")]
     NotPubKey(Value),
 
@@ -51,11 +54,29 @@ pub enum Error {
     #[error("Expected a descriptor, policy or miniscript, not {0:?}")]
     NotDescriptorLike(Value),
 
+    #[error("Cannot compare {0:?} with {1:?}")]
+    InvalidComparison(Box<Value>, Box<Value>),
+
+    #[error("Number out of range: {0} (too large to be represented)")]
+    NumberOutOfRange(String),
+
+    #[error("Integer overflow")]
+    Overflow,
+
+    #[error("Division by zero")]
+    DivideByZero,
+
     #[error("Array index out of range")]
     ArrayIndexOutOfRange,
 
-    #[error("Function {0} expected {1} arguments, not {2}")]
-    ArgumentMismatch(Ident, usize, usize),
+    #[error("Expected {0} arguments, not {1}")]
+    ArgumentMismatch(usize, usize),
+
+    #[error("Function called after the program it was defined in has completed")]
+    ProgramEnded,
+
+    #[error("Maximum call depth of {0} exceeded")]
+    MaxRecursionDepth(usize),
 
     #[error("Invalid datetime string: {0}")]
     InvalidDateTime(chrono::ParseError),
@@ -72,27 +93,95 @@ pub enum Error {
     #[error("Relative by-blocktime timelocks are only supported for up to 33553920 seconds (roughly 1 year)")]
     InvalidDurationTimeOutOfRange,
 
+    #[error("Relative timelock out of range: {0} (expected a BIP68 sequence number with a value between 1 and 65535, optionally with the type flag (1<<22) set)")]
+    InvalidRelLockTimeOutOfRange(usize),
+
+    #[error("Invalid relative timelock: {0:#x} has bits set outside of the BIP68 type flag (1<<22) and 16-bit value")]
+    InvalidRelLockTimeBits(u32),
+
+    #[error("Absolute timelock out of range: {0} (expected a number between 1 and 4294967295)")]
+    InvalidAbsLockTimeOutOfRange(usize),
+
+    #[error("Child code out of range: {0} (unhardened child codes must be below 2^31)")]
+    InvalidChildCode(usize),
+
+    #[error("Invalid escape sequence: \\{0}")]
+    InvalidEscape(char),
+
+    #[error("Unterminated string interpolation, expected a closing }}")]
+    UnterminatedInterpolation,
+
+    #[error("Expected a string, not {0:?}")]
+    NotString(Value),
+
+    #[error("Module not found: {0}")]
+    ImportNotFound(String),
+
+    #[error("Module imported more than once: {0}")]
+    DuplicateImport(String),
+
+    #[error("Circular import of {0}")]
+    CircularImport(String),
+
+    #[error("Imports are unavailable without an import resolver")]
+    ImportUnsupported,
+
+    #[error("{0}: {1}")]
+    ModuleError(String, Box<Error>),
+
     #[error("Parser error: {0}")]
     ParseError(String),
 
     #[error("Invalid arguments")]
     InvalidArguments,
 
+    #[error("Unknown spending resource: {0} (expected keys, preimages, age or time)")]
+    InvalidResource(String),
+
+    #[error("Too many spending paths to enumerate (over {0})")]
+    TooManySpendingPaths(usize),
+
+    #[error("Bare scripts have no address representation")]
+    NotAddressable,
+
     #[error("Descriptors can only be derived with a single child code and without '/*'")]
     InvalidDescriptorDerivation,
 
     #[error("Standalone single keys cannot be derived")]
     InvalidSingleDerivation,
 
-    #[error("sh() can only wrap wsh() or wpkh()")]
+    #[error("sh() can only wrap wsh(), wpkh(), a miniscript or sortedmulti()")]
     InvalidShUse,
 
+    #[error(
+        "Taproot script tree nodes must be a policy, a miniscript or an array of two sub-trees"
+    )]
+    InvalidTapTree,
+
+    #[error("Invalid descriptor checksum: {0} (expected {1})")]
+    InvalidDescriptorChecksum(String, String),
+
+    #[error("Invalid character in descriptor: {0:?}")]
+    InvalidDescriptorChar(char),
+
     #[error("in {0}(): {1}")]
     CallError(Ident, Box<Error>),
 
+    #[error("{1}")]
+    Located(Span, Box<Error>),
+
     #[error("Descriptor key parse error: {0}")]
     DescriptorKeyParseError(DescriptorKeyParseError),
 
+    #[error("Descriptor key conversion error: {0}")]
+    DescriptorConversionError(ConversionError),
+
+    #[error("Invalid miniscript wrapper: {0}")]
+    InvalidWrapper(char),
+
+    #[error("Miniscript error: {0}")]
+    MiniscriptError(miniscript::Error),
+
     #[error("Miniscript compiler error: {0}")]
     MiniscriptCompilerError(CompilerError),
 
@@ -106,19 +195,107 @@ pub enum Error {
     Io(std::io::Error),
 }
 
-impl<L, T, E> From<ParseError<L, T, E>> for Error
+impl Error {
+    /// Attach the source location where the error occurred, unless a more specific one is already known
+    pub fn at(self, span: Span) -> Self {
+        if self.span().is_some() {
+            self
+        } else {
+            Error::Located(span, self.into())
+        }
+    }
+
+    /// Get the innermost source location associated with the error
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Located(span, err) => err.span().or(Some(*span)),
+            Error::CallError(_, err) => err.span(),
+            _ => None,
+        }
+    }
+
+    /// Apply `f` to the source locations associated with the error
+    pub fn map_spans<F: FnMut(&mut Span)>(self, f: &mut F) -> Self {
+        match self {
+            Error::Located(mut span, err) => {
+                f(&mut span);
+                Error::Located(span, err.map_spans(f).into())
+            }
+            Error::CallError(ident, err) => Error::CallError(ident, err.map_spans(f).into()),
+            err => err,
+        }
+    }
+
+    /// Format the error with its `name:line:col` location and a caret-highlighted excerpt from `code`
+    pub fn format_with_source(&self, code: &str, name: &str) -> String {
+        let span = match self.span() {
+            Some(span) => span,
+            None => return format!("{}: {}", name, self),
+        };
+        let (line_num, col) = span.line_col(code);
+        let line = code.lines().nth(line_num - 1).unwrap_or("");
+        // Highlight until the end of the span or the end of the line, whichever comes first
+        let start = floor_char_boundary(code, span.start);
+        let end = floor_char_boundary(code, span.end).max(start);
+        let highlight_len = code[start..end]
+            .lines()
+            .next()
+            .map_or(0, |s| s.chars().count())
+            .max(1);
+        format!(
+            "{}:{}:{}: {}\n  {}\n  {}{}",
+            name,
+            line_num,
+            col,
+            self,
+            line,
+            " ".repeat(col - 1),
+            "^".repeat(highlight_len)
+        )
+    }
+}
+
+impl<T> From<ParseError<usize, T, Error>> for Error
 where
-    L: fmt::Display,
     T: fmt::Display,
-    E: fmt::Display,
 {
-    fn from(err: ParseError<L, T, E>) -> Self {
-        Error::ParseError(err.to_string())
+    fn from(err: ParseError<usize, T, Error>) -> Self {
+        let (msg, span) = match err {
+            ParseError::InvalidToken { location } => {
+                ("Invalid token".into(), Span::new(location, location))
+            }
+            ParseError::UnrecognizedEOF { location, expected } => (
+                format!("Unexpected end of input{}", fmt_expected(&expected)),
+                Span::new(location, location),
+            ),
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => (
+                format!("Unexpected token `{}`{}", token, fmt_expected(&expected)),
+                Span::new(start, end),
+            ),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => (format!("Extra token `{}`", token), Span::new(start, end)),
+            ParseError::User { error } => return error,
+        };
+        Error::ParseError(msg).at(span)
+    }
+}
+
+fn fmt_expected(expected: &[String]) -> String {
+    if expected.is_empty() {
+        String::new()
+    } else {
+        format!(", expected one of {}", expected.join(", "))
     }
 }
 
 impl_from_variant!(DescriptorKeyParseError, Error);
+impl_from_variant!(ConversionError, Error, DescriptorConversionError);
 impl_from_variant!(CompilerError, Error, MiniscriptCompilerError);
+impl_from_variant!(miniscript::Error, Error, MiniscriptError);
 impl_from_variant!(hashes::Error, Error, HashError);
 impl_from_variant!(hashes::hex::Error, Error, HexError);
 impl_from_variant!(chrono::ParseError, Error, InvalidDateTime);
@@ -1,7 +1,11 @@
+use std::fmt;
+use std::sync::Arc;
+
 use crate::ast::{self, Expr, Ident};
 use crate::error::{Error, Result};
+use crate::import::in_module;
 use crate::runtime::{Evaluate, Value};
-use crate::scope::Scope;
+use crate::scope::{CapturedScope, Scope};
 
 #[derive(Debug, Clone)]
 pub enum Function {
@@ -9,19 +13,44 @@ pub enum Function {
     Native(NativeFunction),
 }
 
-/// A user-defined function implemented in Minsc
+/// A user-defined function implemented in Minsc. Evaluated within the lexical scope it was defined in.
 #[derive(Debug, Clone)]
 pub struct UserFunction {
-    pub ident: Ident,
+    /// The function name, or `None` for anonymous functions
+    pub ident: Option<Ident>,
     pub signature: Vec<Ident>,
     pub body: Expr,
+    pub(crate) scope: CapturedScope,
 }
 impl_from_variant!(UserFunction, Function, User);
 
-/// A native function implemented in Rust
-#[derive(Debug, Clone)]
+/// A native function implemented in Rust. Can be a plain function or a closure capturing host state.
+#[derive(Clone)]
 pub struct NativeFunction {
-    pub body: fn(Vec<Value>) -> Result<Value>,
+    pub ident: Ident,
+    pub body: NativeFunctionBody,
+}
+
+pub type NativeFunctionBody = Arc<dyn Fn(Vec<Value>, &Scope) -> Result<Value> + Send + Sync>;
+
+impl NativeFunction {
+    pub fn new<F>(ident: Ident, body: F) -> Self
+    where
+        F: Fn(Vec<Value>, &Scope) -> Result<Value> + Send + Sync + 'static,
+    {
+        NativeFunction {
+            ident,
+            body: Arc::new(body),
+        }
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("ident", &self.ident)
+            .finish()
+    }
 }
 impl_from_variant!(NativeFunction, Function, Native);
 
@@ -39,26 +68,31 @@ impl Call for Function {
 }
 
 impl Call for UserFunction {
-    fn call(&self, args: Vec<Value>, scope: &Scope) -> Result<Value> {
+    fn call(&self, args: Vec<Value>, caller_scope: &Scope) -> Result<Value> {
         if self.signature.len() != args.len() {
-            return Err(Error::ArgumentMismatch(
-                self.ident.clone(),
-                self.signature.len(),
-                args.len(),
-            ));
+            return Err(Error::ArgumentMismatch(self.signature.len(), args.len()));
         }
-        let mut scope = scope.child();
+        let fn_scope = self.scope.get()?;
+        let scope = fn_scope.call_child(caller_scope)?;
         for (index, value) in args.into_iter().enumerate() {
             let ident = self.signature.get(index).unwrap();
             scope.set(ident.clone(), value)?;
         }
-        self.body.eval(&scope)
+        self.body.eval(&scope).map_err(|e| {
+            // Report errors relative to the module the function was defined in, if called from outside of it
+            let fn_module = fn_scope.module().map(|m| &m.path);
+            if fn_module != caller_scope.module().map(|m| &m.path) {
+                in_module(&fn_scope, e)
+            } else {
+                e
+            }
+        })
     }
 }
 
 impl Call for NativeFunction {
-    fn call(&self, args: Vec<Value>, _scope: &Scope) -> Result<Value> {
-        (self.body)(args)
+    fn call(&self, args: Vec<Value>, scope: &Scope) -> Result<Value> {
+        (self.body)(args, scope)
     }
 }
 
@@ -71,13 +105,24 @@ impl Call for Value {
     }
 }
 
-impl From<ast::FnDef> for Function {
-    fn from(fn_def: ast::FnDef) -> Self {
+impl UserFunction {
+    /// Create a function from its definition, capturing the scope it is defined in
+    pub fn new(fn_def: ast::FnDef, scope: &Scope) -> Self {
         UserFunction {
-            ident: fn_def.ident,
+            ident: Some(fn_def.ident),
             signature: fn_def.signature,
             body: fn_def.body,
+            scope: scope.capture(),
+        }
+    }
+
+    /// Create an anonymous function, capturing the scope it is defined in
+    pub fn anonymous(fn_expr: ast::FnExpr, scope: &Scope) -> Self {
+        UserFunction {
+            ident: None,
+            signature: fn_expr.signature,
+            body: *fn_expr.body,
+            scope: scope.capture(),
         }
-        .into()
     }
 }
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+use crate::ast::ExprKind;
+use crate::error::{Error, Result};
+use crate::runtime::Execute;
+use crate::scope::Scope;
+
+/// Loads the source code of imported modules
+pub trait ImportResolver: Send + Sync {
+    /// Resolve `path`, as it appears in the `import` statement, into the path identifying the module.
+    /// `importer` is the resolved path of the importing module, or None when imported by the main program.
+    fn resolve(&self, path: &str, importer: Option<&str>) -> Result<String>;
+
+    /// Get the source code of the module identified by the resolved `path`
+    fn load(&self, path: &str) -> Result<String>;
+}
+
+/// Resolve imports from the filesystem, by looking up relative paths within the importing module's
+/// directory followed by a list of search directories
+#[derive(Debug, Clone, Default)]
+pub struct FsResolver {
+    search_paths: Vec<PathBuf>,
+}
+
+impl FsResolver {
+    pub fn new(search_paths: Vec<PathBuf>) -> Self {
+        FsResolver { search_paths }
+    }
+}
+
+impl ImportResolver for FsResolver {
+    fn resolve(&self, path: &str, importer: Option<&str>) -> Result<String> {
+        let file = if Path::new(path).is_absolute() {
+            Some(PathBuf::from(path))
+        } else {
+            let importer_dir = importer.and_then(|importer| Path::new(importer).parent());
+            importer_dir
+                .into_iter()
+                .chain(self.search_paths.iter().map(PathBuf::as_path))
+                .map(|dir| dir.join(path))
+                .find(|f| f.is_file())
+        };
+        match file {
+            Some(file) if file.is_file() => Ok(file.to_string_lossy().into()),
+            _ => Err(Error::ImportNotFound(path.into())),
+        }
+    }
+
+    fn load(&self, path: &str) -> Result<String> {
+        Ok(fs::read_to_string(path)?)
+    }
+}
+
+/// Resolve imports from an in-memory map of paths to source code
+#[derive(Debug, Default)]
+pub struct MemoryResolver(RwLock<HashMap<String, String>>);
+
+impl MemoryResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a module, replacing any existing module with the same path
+    pub fn insert<P: Into<String>, C: Into<String>>(&self, path: P, code: C) {
+        self.0.write().unwrap().insert(path.into(), code.into());
+    }
+}
+
+impl From<HashMap<String, String>> for MemoryResolver {
+    fn from(modules: HashMap<String, String>) -> Self {
+        MemoryResolver(RwLock::new(modules))
+    }
+}
+
+impl ImportResolver for MemoryResolver {
+    // Paths are looked up relative to the importing module's directory first, then as-is
+    fn resolve(&self, path: &str, importer: Option<&str>) -> Result<String> {
+        let modules = self.0.read().unwrap();
+        let importer_dir = importer.and_then(|importer| Some(&importer[..importer.rfind('/')?]));
+        importer_dir
+            .map(|dir| format!("{}/{}", dir, path))
+            .into_iter()
+            .chain(std::iter::once(path.into()))
+            .find(|path| modules.contains_key(path))
+            .ok_or_else(|| Error::ImportNotFound(path.into()))
+    }
+
+    fn load(&self, path: &str) -> Result<String> {
+        let modules = self.0.read().unwrap();
+        let code = modules.get(path);
+        code.cloned()
+            .ok_or_else(|| Error::ImportNotFound(path.into()))
+    }
+}
+
+// The source ids assigned to loaded modules, with 0 reserved for the main program
+static NEXT_SOURCE_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Load and evaluate the module at `path`, returning its scope with the definitions it exports.
+/// Modules are evaluated once per program, later imports of the same path reuse the loaded module.
+pub fn load_module(path: &str, importer: &Scope) -> Result<Scope> {
+    let resolver = importer.resolver().ok_or(Error::ImportUnsupported)?;
+    let path = &resolver.resolve(path, importer.module().map(|m| m.path.as_str()))?;
+    if let Some(module) = importer.loaded_module(path) {
+        return Ok(module);
+    }
+    ensure!(
+        !importer.is_importing(path),
+        Error::CircularImport(path.into())
+    );
+
+    let code = resolver.load(path)?;
+    let mut program = crate::parse(&code).map_err(|e| module_error(path, &code, e))?;
+
+    // Mark the spans as belonging to the module, to tell its errors apart from errors raised by
+    // code from other sources that it calls into (like functions passed to it as arguments)
+    let source = NEXT_SOURCE_ID.fetch_add(1, Ordering::Relaxed);
+    program.for_each_span(&mut |span| span.source = source);
+    let scope = importer.module_child(path, code, source);
+
+    // Only the statements are executed, a final return value (if any) is ignored
+    if let ExprKind::Block(block) = program.kind {
+        for stmt in &block.stmts {
+            stmt.exec(&scope).map_err(|e| in_module(&scope, e))?;
+        }
+    }
+    importer.add_loaded_module(path, scope.clone());
+    Ok(scope)
+}
+
+/// Errors raised by code from a module carry source locations relative to the module's code. This
+/// wraps them with the `path:line:col` of the error within the module, if `scope` belongs to one
+/// and the error was raised by the module's own code.
+pub fn in_module(scope: &Scope, err: Error) -> Error {
+    match (scope.module(), err.span()) {
+        (Some(module), Some(span)) if span.source == module.source => {
+            module_error(&module.path, &module.code, err)
+        }
+        _ => err,
+    }
+}
+
+fn module_error(path: &str, code: &str, err: Error) -> Error {
+    let location = match err.span() {
+        Some(span) => {
+            let (line, col) = span.line_col(code);
+            format!("{}:{}:{}", path, line, col)
+        }
+        None => path.into(),
+    };
+    Error::ModuleError(location, err.into())
+}
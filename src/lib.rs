@@ -11,12 +11,16 @@ lalrpop_mod!(
 
 #[macro_use]
 mod macros;
+pub mod analyze;
 pub mod ast;
 pub mod builtins;
+pub mod checksum;
 pub mod error;
 pub mod function;
+pub mod import;
 pub mod runtime;
 pub mod scope;
+pub mod taproot;
 pub mod time;
 pub mod util;
 
@@ -25,7 +29,7 @@ pub mod playground;
 #[cfg(feature = "wasm")]
 pub mod wasm;
 
-pub use ast::{Expr, Ident};
+pub use ast::{Expr, Ident, Span};
 pub use error::{Error, Result};
 pub use runtime::{Evaluate, Value};
 pub use scope::Scope;
@@ -35,6 +39,8 @@ use miniscript::{descriptor, policy};
 pub type Policy = policy::concrete::Policy<descriptor::DescriptorPublicKey>;
 pub type Miniscript = miniscript::Miniscript<descriptor::DescriptorPublicKey, miniscript::Segwitv0>;
 pub type Descriptor = descriptor::Descriptor<descriptor::DescriptorPublicKey>;
+pub type SortedMulti =
+    descriptor::SortedMultiVec<descriptor::DescriptorPublicKey, miniscript::Segwitv0>;
 
 pub fn parse(s: &str) -> Result<Expr> {
     let parser = grammar::ProgramParser::new();
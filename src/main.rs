@@ -1,17 +1,51 @@
-use minsc::{eval, parse, Result};
-use std::{env, fs, io};
+use minsc::analyze::{check, counterexample, lift, spending_paths, SpendingPath};
+use minsc::import::FsResolver;
+use minsc::{parse, Evaluate, Policy, Result, Scope, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{env, fs, io, process};
 
 fn main() -> Result<()> {
     let mut args = env::args();
     let input = args.nth(1).unwrap_or_else(|| "-".into());
 
-    let arg = args.next();
-    let print_ast = arg == Some("--ast".into());
-    let debug = arg == Some("--debug".into());
+    let mut print_ast = false;
+    let mut debug = false;
+    let mut print_paths = false;
+    let mut run_checks = false;
+    let mut equivalent_to = None;
+    let mut include_dirs = vec![];
+
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--ast" => print_ast = true,
+            "--debug" => debug = true,
+            "--paths" => print_paths = true,
+            "--check" => run_checks = true,
+            "--equivalent" => match args.next() {
+                Some(other) => equivalent_to = Some(other),
+                None => {
+                    eprintln!("Missing file for {}", arg);
+                    process::exit(1);
+                }
+            },
+            "-I" | "--include" => match args.next() {
+                Some(dir) => include_dirs.push(dir.into()),
+                None => {
+                    eprintln!("Missing directory for {}", arg);
+                    process::exit(1);
+                }
+            },
+            _ => {
+                eprintln!("Unknown argument: {}", arg);
+                process::exit(1);
+            }
+        }
+    }
 
     let mut reader: Box<dyn io::Read> = match &*input {
         "-" => Box::new(io::stdin()),
-        _ => Box::new(fs::File::open(input)?),
+        _ => Box::new(fs::File::open(&input)?),
     };
 
     let mut code = String::new();
@@ -20,7 +54,63 @@ fn main() -> Result<()> {
     if print_ast {
         println!("{:#?}", parse(&code));
     } else {
-        let res = eval(parse(&code)?)?;
+        let res = eval_or_exit(&code, &input, &include_dirs);
+        if print_paths {
+            match res.into_policy().and_then(|policy| spending_paths(&policy)) {
+                Ok(paths) => print_paths_table(&paths),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        if run_checks {
+            let findings = check(res).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(1);
+            });
+            if findings.is_empty() {
+                println!("No issues found");
+                return Ok(());
+            }
+            for finding in &findings {
+                println!("{}", finding);
+            }
+            process::exit(1);
+        }
+        if let Some(other) = equivalent_to {
+            let other_res = eval_or_exit(&fs::read_to_string(&other)?, &other, &include_dirs);
+            let (a, b) = match (lift(res), lift(other_res)) {
+                (Ok(a), Ok(b)) => (a, b),
+                (Err(e), _) | (_, Err(e)) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            };
+            match counterexample(&a, &b) {
+                Ok(None) => println!("Equivalent"),
+                Ok(Some(cex)) => {
+                    let conditions: Vec<_> = cex.conditions.iter().map(Policy::to_string).collect();
+                    let (satisfied, unsatisfied) = match cex.satisfies_first {
+                        true => (&input, &other),
+                        false => (&other, &input),
+                    };
+                    println!(
+                        "Not equivalent: {} satisfies {} but not {}",
+                        conditions.join(" && "),
+                        satisfied,
+                        unsatisfied
+                    );
+                    process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+            return Ok(());
+        }
         println!("{}", res);
         if debug {
             println!("\n\n{:#?}", res);
@@ -29,3 +119,66 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+// Evaluate the code, or print the error and exit. Imports are resolved relative to the file's
+// directory, followed by any `-I <dir>` search paths.
+fn eval_or_exit(code: &str, name: &str, include_dirs: &[PathBuf]) -> Value {
+    let dir = match name {
+        "-" => PathBuf::from("."),
+        _ => Path::new(name).parent().unwrap_or(Path::new(".")).into(),
+    };
+    let search_paths = std::iter::once(dir).chain(include_dirs.iter().cloned());
+    let scope = Scope::root_with_resolver(Arc::new(FsResolver::new(search_paths.collect())));
+    match parse(code).and_then(|expr| expr.eval(&scope)) {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("{}", e.format_with_source(code, name));
+            process::exit(1);
+        }
+    }
+}
+
+// Print the spending paths as a table, with the conditions grouped by type
+fn print_paths_table(paths: &[SpendingPath]) {
+    let rows: Vec<[String; 4]> = paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let join = |filter: fn(&&Policy) -> bool| {
+                let conds: Vec<_> = path.iter().filter(filter).map(Policy::to_string).collect();
+                if conds.is_empty() {
+                    "-".into()
+                } else {
+                    conds.join(", ")
+                }
+            };
+            [
+                (i + 1).to_string(),
+                join(|c| matches!(c, Policy::Key(_))),
+                join(|c| matches!(c, Policy::After(_) | Policy::Older(_))),
+                join(|c| !matches!(c, Policy::Key(_) | Policy::After(_) | Policy::Older(_))),
+            ]
+        })
+        .collect();
+
+    let header = [
+        "#".to_string(),
+        "Keys".into(),
+        "Timelocks".into(),
+        "Hashlocks".into(),
+    ];
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<_> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
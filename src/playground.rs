@@ -1,12 +1,14 @@
 use miniscript::bitcoin::hashes::hex::FromHex;
-use miniscript::bitcoin::{Network, Script};
-use miniscript::descriptor::Descriptor;
+use miniscript::bitcoin::{Address, Network, Script};
+use miniscript::DescriptorTrait;
 use serde::Serialize;
 use std::str::FromStr;
 use wasm_bindgen::prelude::*;
 
-use crate::util::get_descriptor_ctx;
-use crate::{parse, Evaluate, Result, Scope, Value};
+use crate::analyze;
+use crate::builtins::check_top_level;
+use crate::util::derive_descriptor;
+use crate::{parse, Descriptor, Evaluate, Result, Scope, Value};
 
 #[derive(Serialize)]
 pub struct PlaygroundResult {
@@ -17,41 +19,74 @@ pub struct PlaygroundResult {
     descriptor: Option<String>,
     address: Option<String>,
     other: Option<String>,
+    script_size: Option<usize>,
+    op_count: Option<usize>,
+    max_weight: Option<usize>,
+    path_weights: Option<Vec<(String, Option<usize>)>>,
+    too_many_paths: bool,
 }
 
+// Weighing the spending paths is the slowest part of the analysis, keep it quick to allow live updates
+const MAX_PLAYGROUND_PATHS: usize = 100;
+
 #[wasm_bindgen]
 pub fn run_playground(code: &str, network: &str) -> std::result::Result<JsValue, JsValue> {
     let network = Network::from_str(network).map_err(|e| e.to_string())?;
-    let ctx = get_descriptor_ctx(0);
+    // Bare descriptors have no address representation
+    let address = |desc: &Descriptor| -> Option<Address> {
+        derive_descriptor(desc, 0).ok()?.address(network).ok()
+    };
 
-    let value = run(code).map_err(|e| e.to_string())?;
+    let value = run(code).map_err(|e| e.format_with_source(code, "<input>"))?;
 
     let (policy, miniscript, desc, addr, other) = match value {
         Value::Policy(policy) => {
             let miniscript = policy.compile().map_err(|e| e.to_string())?;
-            let desc = Descriptor::Wsh(miniscript.clone());
-            let addr = desc.address(network, ctx).unwrap();
-            (Some(policy), Some(miniscript), Some(desc), Some(addr), None)
+            let desc = Descriptor::new_wsh(miniscript.clone()).map_err(|e| e.to_string())?;
+            let addr = address(&desc);
+            (Some(policy), Some(miniscript), Some(desc), addr, None)
+        }
+        // Miniscript fragments that aren't valid as a top-level script are shown without a descriptor
+        Value::Miniscript(miniscript) if check_top_level(&miniscript).is_err() => {
+            (None, Some(miniscript), None, None, None)
         }
         Value::Miniscript(miniscript) => {
-            let desc = Descriptor::Wsh(miniscript.clone());
-            let addr = desc.address(network, ctx).unwrap();
-            (None, Some(miniscript), Some(desc), Some(addr), None)
+            let desc = Descriptor::new_wsh(miniscript.clone()).map_err(|e| e.to_string())?;
+            let addr = address(&desc);
+            (None, Some(miniscript), Some(desc), addr, None)
+        }
+        Value::SortedMulti(smv) => {
+            let desc =
+                Descriptor::new_wsh_sortedmulti(smv.k, smv.pks).map_err(|e| e.to_string())?;
+            let addr = address(&desc);
+            (None, None, Some(desc), addr, None)
         }
         Value::Descriptor(desc) => {
-            let addr = desc.address(network, ctx).unwrap();
-            (None, None, Some(desc), Some(addr), None)
+            let addr = address(&desc);
+            (None, None, Some(desc), addr, None)
         }
         Value::PubKey(key) => {
-            let desc = Descriptor::Wpkh(key.clone());
-            let addr = desc.address(network, ctx).unwrap();
-            (None, None, Some(desc), Some(addr), Some(key.into()))
+            let desc = Descriptor::new_wpkh(key.clone()).map_err(|e| e.to_string())?;
+            let addr = address(&desc);
+            (None, None, Some(desc), addr, Some(key.into()))
         }
         Value::Address(addr) => (None, None, None, Some(addr), None),
         other => (None, None, None, None, Some(other)),
     };
 
-    let script = desc.as_ref().map(|d| d.witness_script(ctx));
+    // Taproot descriptors have no single script to show
+    let script = desc
+        .as_ref()
+        .and_then(|d| derive_descriptor(d, 0).ok()?.explicit_script().ok());
+    let cost = desc
+        .as_ref()
+        .map(|d| analyze::cost(d, MAX_PLAYGROUND_PATHS))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    let too_many_paths = matches!(
+        cost.as_ref().map(|c| &c.paths),
+        Some(analyze::PathWeights::TooMany)
+    );
 
     Ok(JsValue::from_serde(&PlaygroundResult {
         policy: policy.map(|p| p.to_string()),
@@ -61,6 +96,21 @@ pub fn run_playground(code: &str, network: &str) -> std::result::Result<JsValue,
         script_asm: script.as_ref().map(get_script_asm),
         address: addr.map(|a| a.to_string()),
         other: other.map(|o| o.to_string()),
+        script_size: cost.as_ref().map(|c| c.script_size),
+        op_count: cost.as_ref().and_then(|c| c.op_count),
+        max_weight: cost.as_ref().and_then(|c| c.max_weight),
+        path_weights: cost.map(|c| match c.paths {
+            analyze::PathWeights::Paths(paths) => paths
+                .into_iter()
+                .map(|(path, weight)| (fmt_path(&path), weight))
+                .collect(),
+            analyze::PathWeights::Multi(k, pks, weight) => {
+                let keys: Vec<_> = pks.iter().map(|pk| pk.to_string()).collect();
+                vec![(format!("any {} of {}", k, keys.join(", ")), weight)]
+            }
+            analyze::PathWeights::TooMany => vec![],
+        }),
+        too_many_paths,
     })
     .unwrap())
 }
@@ -69,6 +119,11 @@ fn run(code: &str) -> Result<Value> {
     parse(code)?.eval(&DEMO_SCOPE)
 }
 
+fn fmt_path(path: &analyze::SpendingPath) -> String {
+    let conditions: Vec<_> = path.iter().map(|c| c.to_string()).collect();
+    conditions.join(" && ")
+}
+
 fn get_script_asm(script: &Script) -> String {
     let s = format!("{:?}", script);
     s[7..s.len() - 1].into()
@@ -76,9 +131,9 @@ fn get_script_asm(script: &Script) -> String {
 
 lazy_static! {
     // Provide some built-in example pubkeys and hashes in the web demo env
-    static ref DEMO_SCOPE: Scope<'static> = {
-        let mut scope = Scope::root();
-        let mut add_key = |name, key: &str| {
+    static ref DEMO_SCOPE: Scope = {
+        let scope = Scope::root_with_resolver(crate::wasm::MODULES.clone());
+        let add_key = |name, key: &str| {
             scope
                 .set(name, Value::PubKey(key.parse().unwrap()))
                 .unwrap();
@@ -164,7 +219,7 @@ lazy_static! {
             "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw/9/0",
         );
 
-        let mut add_hash = |name, hash: &str| {
+        let add_hash = |name, hash: &str| {
             scope
                 .set(name, Value::Hash(Vec::from_hex(hash).unwrap()))
                 .unwrap();
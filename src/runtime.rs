@@ -1,16 +1,22 @@
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
 
 use miniscript::bitcoin::hashes::{self, hex::FromHex, hex::ToHex, Hash};
 use miniscript::bitcoin::{Address, Network, Script};
-use miniscript::descriptor::DescriptorPublicKey;
-
-use crate::ast::{self, Expr, Stmt};
-use crate::builtins::fns;
-use crate::function::{Call, Function};
-use crate::util::get_descriptor_ctx;
-use crate::{Descriptor, Error, Miniscript, Policy, Result, Scope};
+use miniscript::descriptor::{DescriptorPublicKey, Wildcard};
+use miniscript::DescriptorTrait;
+
+use crate::analyze;
+use crate::ast::{self, Expr, ExprKind, Ident, InfixOp, Stmt};
+use crate::builtins::{apply_wrappers, check_top_level, into_fragment};
+use crate::function::{Call, Function, UserFunction};
+use crate::import::load_module;
+use crate::taproot::PolicyTree;
+use crate::time::{duration_to_seq, is_blocktime_seq, parse_datetime};
+use crate::util::derive_descriptor;
+use crate::{Descriptor, Error, Miniscript, Policy, Result, Scope, SortedMulti};
 
 /// A runtime value. This is what gets passed around as function arguments, returned from functions,
 /// and assigned to variables.
@@ -19,6 +25,8 @@ pub enum Value {
     PubKey(DescriptorPublicKey),
     Hash(Vec<u8>),
     Number(usize),
+    Bool(bool),
+    String(String),
     DateTime(String),
     Duration(ast::Duration),
 
@@ -26,6 +34,7 @@ pub enum Value {
     WithProb(usize, Policy),
 
     Miniscript(Miniscript),
+    SortedMulti(SortedMulti),
     Descriptor(Descriptor),
     Script(Script),
     Address(Address),
@@ -39,6 +48,7 @@ pub enum Value {
 
 impl_from_variant!(Policy, Value);
 impl_from_variant!(Miniscript, Value);
+impl_from_variant!(SortedMulti, Value);
 impl_from_variant!(Descriptor, Value);
 impl_from_variant!(DescriptorPublicKey, Value, PubKey);
 impl_from_variant!(Script, Value);
@@ -47,10 +57,22 @@ impl_from_variant!(Function, Value);
 impl_from_variant!(Array, Value);
 impl_from_variant!(Network, Value);
 impl_from_variant!(usize, Value, Number);
+impl_from_variant!(bool, Value, Bool);
+impl_from_variant!(String, Value);
 
 #[derive(Debug, Clone)]
 pub struct Array(pub Vec<Value>);
 
+// Taproot trees are represented as nested arrays of two sub-trees, as accepted by tr()
+impl From<PolicyTree> for Value {
+    fn from(tree: PolicyTree) -> Self {
+        match tree {
+            PolicyTree::Leaf(policy) => policy.into(),
+            PolicyTree::Branch(left, right) => Array(vec![(*left).into(), (*right).into()]).into(),
+        }
+    }
+}
+
 /// Evaluate an expression. Expressions have no side-effects and return a value.
 pub trait Evaluate {
     fn eval(&self, scope: &Scope) -> Result<Value>;
@@ -58,31 +80,55 @@ pub trait Evaluate {
 
 /// Execute a statement. Statements have side-effects and don't have a return value.
 pub trait Execute {
-    fn exec(&self, scope: &mut Scope) -> Result<()>;
+    fn exec(&self, scope: &Scope) -> Result<()>;
 }
 
 impl Execute for ast::Assign {
-    fn exec(&self, scope: &mut Scope) -> Result<()> {
+    fn exec(&self, scope: &Scope) -> Result<()> {
         for assignment in &self.0 {
             let value = assignment.rhs.eval(scope)?;
-            scope.set(assignment.lhs.clone(), value)?;
+            scope
+                .set(assignment.lhs.clone(), value)
+                .map_err(|e| e.at(assignment.span))?;
         }
         Ok(())
     }
 }
 
 impl Execute for ast::FnDef {
-    fn exec(&self, scope: &mut Scope) -> Result<()> {
-        let func = Function::from(self.clone());
-        scope.set(self.ident.clone(), func.into())
+    fn exec(&self, scope: &Scope) -> Result<()> {
+        let func = Function::from(UserFunction::new(self.clone(), scope));
+        scope
+            .set(self.ident.clone(), func.into())
+            .map_err(|e| e.at(self.span))
+    }
+}
+
+impl Execute for ast::Import {
+    fn exec(&self, scope: &Scope) -> Result<()> {
+        scope
+            .add_import(&self.path, self.alias.as_ref())
+            .map_err(|e| e.at(self.span))?;
+        let module = load_module(&self.path, scope).map_err(|e| e.at(self.span))?;
+        for (ident, value) in module.exports() {
+            let ident = match &self.alias {
+                Some(alias) => Ident(format!("{}.{}", alias, ident)),
+                None => ident,
+            };
+            scope
+                .set_imported(ident, value)
+                .map_err(|e| e.at(self.span))?;
+        }
+        Ok(())
     }
 }
 
 impl Execute for Stmt {
-    fn exec(&self, scope: &mut Scope) -> Result<()> {
+    fn exec(&self, scope: &Scope) -> Result<()> {
         match self {
             Stmt::FnDef(x) => x.exec(scope),
             Stmt::Assign(x) => x.exec(scope),
+            Stmt::Import(x) => x.exec(scope),
         }
     }
 }
@@ -112,10 +158,9 @@ fn eval_andor(name: &str, thresh_n: usize, policies: &[Expr], scope: &Scope) ->
         call(scope, &name.into(), policies)
     } else {
         // delegate to thresh() when there are more
-        let thresh_n = ast::Expr::Number(thresh_n);
-        let mut args = vec![&thresh_n];
-        args.extend(policies);
-        call(scope, &"thresh".into(), &args)
+        let mut args = vec![Value::Number(thresh_n)];
+        args.extend(eval_exprs(scope, policies)?);
+        call_args(scope, &"thresh".into(), args)
     }
 }
 
@@ -127,10 +172,9 @@ impl Evaluate for ast::Thresh {
 
 impl Evaluate for ast::Ident {
     fn eval(&self, scope: &Scope) -> Result<Value> {
-        Ok(match scope.get(&self) {
-            Some(binding) => binding.clone(),
-            None => bail!(Error::VarNotFound(self.clone())),
-        })
+        scope
+            .get(self)
+            .ok_or_else(|| Error::VarNotFound(self.clone()))
     }
 }
 impl Evaluate for ast::Array {
@@ -159,17 +203,30 @@ impl Evaluate for ast::ChildDerive {
     fn eval(&self, scope: &Scope) -> Result<Value> {
         let parent = self.parent.eval(scope)?;
 
+        // Numeric division
+        if let Value::Number(mut num) = parent {
+            ensure!(!self.is_wildcard, Error::NotPubKey(parent));
+            for divisor in &self.path {
+                let divisor = divisor.eval(scope)?.into_usize()?;
+                num = num.checked_div(divisor).ok_or(Error::DivideByZero)?;
+            }
+            Ok(num.into())
+        }
         // Derive xpubs children
-        if let Value::PubKey(key) = parent {
+        else if let Value::PubKey(key) = parent {
             let mut xpub = match key {
                 DescriptorPublicKey::XPub(xpub) => xpub,
                 DescriptorPublicKey::SinglePub(_) => bail!(Error::InvalidSingleDerivation),
             };
             for child in &self.path {
-                let child = child.eval(scope)?.into_usize()? as u32;
+                let child = eval_child_code(child, scope)?;
                 xpub.derivation_path = xpub.derivation_path.into_child(child.into());
             }
-            xpub.is_wildcard = self.is_wildcard;
+            xpub.wildcard = if self.is_wildcard {
+                Wildcard::Unhardened
+            } else {
+                Wildcard::None
+            };
             Ok(DescriptorPublicKey::XPub(xpub).into())
         }
         // Derive descriptor children
@@ -180,65 +237,239 @@ impl Evaluate for ast::ChildDerive {
                 Error::InvalidDescriptorDerivation
             );
             let desc = parent.into_desc()?;
-            let child = self.path[0].eval(scope)?.into_usize()? as u32;
-            let desc = desc.derive(child.into());
+            let child = eval_child_code(&self.path[0], scope)?;
+            let desc = desc.derive(child);
             Ok(desc.into())
         }
         // TODO support hardened child codes
     }
 }
 
-impl Evaluate for ast::Block {
+// Hardened derivation is unsupported, child codes must be in the unhardened range
+fn eval_child_code(expr: &Expr, scope: &Scope) -> Result<u32> {
+    let child = expr.eval(scope)?.into_usize()?;
+    ensure!(
+        child < (1 << 31),
+        Error::InvalidChildCode(child).at(expr.span)
+    );
+    Ok(child as u32)
+}
+
+impl Evaluate for ast::FnExpr {
     fn eval(&self, scope: &Scope) -> Result<Value> {
-        let mut scope = scope.child();
-        for stmt in &self.stmts {
-            stmt.exec(&mut scope)?;
+        Ok(Function::from(UserFunction::anonymous(self.clone(), scope)).into())
+    }
+}
+
+impl Evaluate for ast::Infix {
+    fn eval(&self, scope: &Scope) -> Result<Value> {
+        match self.op {
+            InfixOp::And | InfixOp::Or => logical(self, scope),
+            op => infix(op, self.lhs.eval(scope)?, self.rhs.eval(scope)?),
+        }
+    }
+}
+
+// The boolean operators short-circuit, evaluating the rhs only if necessary
+fn logical(infix: &ast::Infix, scope: &Scope) -> Result<Value> {
+    let lhs = infix.lhs.eval(scope)?.into_bool()?;
+    Ok(match infix.op {
+        InfixOp::And => lhs && infix.rhs.eval(scope)?.into_bool()?,
+        InfixOp::Or => lhs || infix.rhs.eval(scope)?.into_bool()?,
+        _ => unreachable!(),
+    }
+    .into())
+}
+
+fn infix(op: InfixOp, lhs: Value, rhs: Value) -> Result<Value> {
+    Ok(match (op, lhs, rhs) {
+        (InfixOp::Add, Value::String(a), Value::String(b)) => (a + &b).into(),
+        (InfixOp::Add | InfixOp::Sub | InfixOp::Mul | InfixOp::Mod, a, b) => {
+            arithmetic(op, a.into_usize()?, b.into_usize()?)?.into()
         }
-        if let Some(return_value) = &self.return_value {
-            // The return value is the final expression within the function body,
-            // optionally prefixed with the `return` keyword
-            return_value.eval(&scope)
-        } else if let Some(Value::Function(func)) = scope.get(&"main".into()) {
-            // The return value is the evaluation of main()
-            func.call(vec![], &scope)
+        (op, a, b) => compare(op, a, b)?.into(),
+    })
+}
+
+impl Evaluate for ast::Not {
+    fn eval(&self, scope: &Scope) -> Result<Value> {
+        Ok((!self.0.eval(scope)?.into_bool()?).into())
+    }
+}
+
+impl Evaluate for ast::If {
+    fn eval(&self, scope: &Scope) -> Result<Value> {
+        if self.condition.eval(scope)?.into_bool()? {
+            self.then_val.eval(scope)
         } else {
-            Err(Error::NoReturnValue)
+            self.else_val.eval(scope)
         }
     }
 }
 
-impl Evaluate for Expr {
+impl Evaluate for ast::Wrap {
+    fn eval(&self, scope: &Scope) -> Result<Value> {
+        let miniscript = into_fragment(self.expr.eval(scope)?)?;
+        Ok(apply_wrappers(&self.wrappers, miniscript)?.into())
+    }
+}
+
+fn arithmetic(op: InfixOp, lhs: usize, rhs: usize) -> Result<usize> {
+    match op {
+        InfixOp::Add => lhs.checked_add(rhs).ok_or(Error::Overflow),
+        InfixOp::Sub => lhs.checked_sub(rhs).ok_or(Error::Overflow),
+        InfixOp::Mul => lhs.checked_mul(rhs).ok_or(Error::Overflow),
+        InfixOp::Mod => lhs.checked_rem(rhs).ok_or(Error::DivideByZero),
+        _ => unreachable!(),
+    }
+}
+
+/// Compare two values. Numbers, durations and datetimes can be ordered, other types only support equality.
+fn compare(op: InfixOp, lhs: Value, rhs: Value) -> Result<bool> {
+    let ordering = match (&lhs, &rhs) {
+        (Value::Number(a), Value::Number(b)) => Some(a.cmp(b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::DateTime(a), Value::DateTime(b)) => {
+            Some(parse_datetime(a)?.cmp(&parse_datetime(b)?))
+        }
+        (Value::Duration(a), Value::Duration(b)) => {
+            let (a, b) = (duration_to_seq(a)?, duration_to_seq(b)?);
+            // block-height and block-time durations are incomparable
+            ensure!(
+                is_blocktime_seq(a) == is_blocktime_seq(b),
+                Error::InvalidComparison(lhs.into(), rhs.into())
+            );
+            Some(a.cmp(&b))
+        }
+        _ => None,
+    };
+    if let Some(ordering) = ordering {
+        return Ok(match op {
+            InfixOp::Eq => ordering == Ordering::Equal,
+            InfixOp::NotEq => ordering != Ordering::Equal,
+            InfixOp::Lt => ordering == Ordering::Less,
+            InfixOp::Gt => ordering == Ordering::Greater,
+            InfixOp::Lte => ordering != Ordering::Greater,
+            InfixOp::Gte => ordering != Ordering::Less,
+            _ => unreachable!(),
+        });
+    }
+
+    let is_equal = match (&lhs, &rhs) {
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::PubKey(a), Value::PubKey(b)) => a == b,
+        (Value::Hash(a), Value::Hash(b)) => a == b,
+        (Value::Policy(a), Value::Policy(b)) => a == b,
+        (Value::Miniscript(a), Value::Miniscript(b)) => a == b,
+        (Value::Descriptor(a), Value::Descriptor(b)) => a == b,
+        (Value::Script(a), Value::Script(b)) => a == b,
+        (Value::Address(a), Value::Address(b)) => a == b,
+        (Value::Network(a), Value::Network(b)) => a == b,
+        _ => bail!(Error::InvalidComparison(lhs.into(), rhs.into())),
+    };
+    match op {
+        InfixOp::Eq => Ok(is_equal),
+        InfixOp::NotEq => Ok(!is_equal),
+        _ => Err(Error::InvalidComparison(lhs.into(), rhs.into())),
+    }
+}
+
+impl Evaluate for ast::Interpolation {
+    fn eval(&self, scope: &Scope) -> Result<Value> {
+        let mut s = String::new();
+        for part in &self.0 {
+            match part.eval(scope)? {
+                // Strings are interpolated as-is, without the quotes used by Display
+                Value::String(part) => s.push_str(&part),
+                part => s.push_str(&part.to_string()),
+            }
+        }
+        Ok(s.into())
+    }
+}
+
+impl Evaluate for ast::Block {
+    fn eval(&self, scope: &Scope) -> Result<Value> {
+        if scope.in_program() {
+            eval_block(self, &scope.child())
+        } else {
+            // Blocks evaluated outside of a running program start a new one, which owns the scopes
+            // created during its evaluation and frees them once it completes
+            let (scope, _program) = scope.program_child();
+            eval_block(self, &scope)
+        }
+    }
+}
+
+fn eval_block(block: &ast::Block, scope: &Scope) -> Result<Value> {
+    for stmt in &block.stmts {
+        stmt.exec(scope)?;
+    }
+    if let Some(return_value) = &block.return_value {
+        // The return value is the final expression within the function body,
+        // optionally prefixed with the `return` keyword
+        return_value.eval(scope)
+    } else if let Some(Value::Function(func)) = scope.get(&"main".into()) {
+        // The return value is the evaluation of main()
+        func.call(vec![], scope)
+    } else {
+        Err(Error::NoReturnValue)
+    }
+}
+
+impl Evaluate for ExprKind {
     fn eval(&self, scope: &Scope) -> Result<Value> {
-        Ok(match self {
-            Expr::Ident(x) => x.eval(scope)?,
-            Expr::Call(x) => x.eval(scope)?,
-            Expr::Or(x) => x.eval(scope)?,
-            Expr::And(x) => x.eval(scope)?,
-            Expr::Thresh(x) => x.eval(scope)?,
-            Expr::Block(x) => x.eval(scope)?,
-            Expr::WithProb(x) => x.eval(scope)?,
-            Expr::Array(x) => x.eval(scope)?,
-            Expr::ArrayAccess(x) => x.eval(scope)?,
-            Expr::ChildDerive(x) => x.eval(scope)?,
+        // Dispatched through a trait object rather than inlining every expression type's evaluation,
+        // which keeps the stack frames of recursive evaluations small
+        let expr: &dyn Evaluate = match self {
+            ExprKind::Ident(x) => x,
+            ExprKind::Call(x) => x,
+            ExprKind::Or(x) => x,
+            ExprKind::And(x) => x,
+            ExprKind::Thresh(x) => x,
+            ExprKind::Block(x) => x,
+            ExprKind::WithProb(x) => x,
+            ExprKind::Array(x) => x,
+            ExprKind::ArrayAccess(x) => x,
+            ExprKind::ChildDerive(x) => x,
+            ExprKind::FnExpr(x) => x,
+            ExprKind::Infix(x) => x,
+            ExprKind::Not(x) => x,
+            ExprKind::If(x) => x,
+            ExprKind::Interpolation(x) => x,
+            ExprKind::Wrap(x) => x,
 
             // Atoms
-            Expr::PubKey(x) => Value::PubKey(x.parse()?),
-            Expr::Hash(x) => Value::Hash(Vec::from_hex(&x)?),
-            Expr::Number(x) => Value::Number(*x),
-            Expr::Duration(x) => Value::Duration(x.clone()),
-            Expr::DateTime(x) => Value::DateTime(x.clone()),
-        })
+            ExprKind::PubKey(x) => return Ok(Value::PubKey(x.parse()?)),
+            ExprKind::Hash(x) => return Ok(Value::Hash(Vec::from_hex(x)?)),
+            ExprKind::Number(x) => return Ok(Value::Number(*x)),
+            ExprKind::Bool(x) => return Ok(Value::Bool(*x)),
+            ExprKind::String(x) => return Ok(Value::String(x.clone())),
+            ExprKind::Duration(x) => return Ok(Value::Duration(x.clone())),
+            ExprKind::DateTime(x) => return Ok(Value::DateTime(x.clone())),
+        };
+        expr.eval(scope)
+    }
+}
+
+impl Evaluate for Expr {
+    fn eval(&self, scope: &Scope) -> Result<Value> {
+        self.kind.eval(scope).map_err(|e| e.at(self.span))
     }
 }
 
 /// Call the function with the given expressions evaluated into values
 fn call<T: Borrow<Expr>>(scope: &Scope, ident: &ast::Ident, exprs: &[T]) -> Result<Value> {
+    let args = eval_exprs(scope, exprs)?;
+    call_args(scope, ident, args)
+}
+
+/// Call the function with the given argument values
+fn call_args(scope: &Scope, ident: &ast::Ident, args: Vec<Value>) -> Result<Value> {
     let func = scope
         .get(ident)
         .ok_or_else(|| Error::FnNotFound(ident.clone()))?;
 
-    let args = eval_exprs(scope, exprs)?;
-
     func.call(args, scope)
         .map_err(|e| Error::CallError(ident.clone(), e.into()))
 }
@@ -253,7 +484,14 @@ impl TryFrom<Value> for Policy {
     fn try_from(value: Value) -> Result<Self> {
         match value {
             Value::Policy(policy) => Ok(policy),
-            arr @ Value::Array(Array(_)) => fns::all(vec![arr])?.try_into(),
+            // Arrays are coerced into an all() policy
+            Value::Array(Array(elements)) => {
+                let policies = elements
+                    .into_iter()
+                    .map(Value::into_policy)
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Policy::Threshold(policies.len(), policies))
+            }
             v => Err(Error::NotPolicyLike(v)),
         }
     }
@@ -269,6 +507,26 @@ impl TryFrom<Value> for usize {
     }
 }
 
+impl TryFrom<Value> for String {
+    type Error = Error;
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::String(s) => Ok(s),
+            v => Err(Error::NotString(v)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = Error;
+    fn try_from(value: Value) -> Result<Self> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            v => Err(Error::NotBool(v)),
+        }
+    }
+}
+
 impl TryFrom<Value> for DescriptorPublicKey {
     type Error = Error;
     fn try_from(value: Value) -> Result<Self> {
@@ -283,9 +541,13 @@ impl TryFrom<Value> for Descriptor {
     fn try_from(value: Value) -> Result<Self> {
         match value {
             Value::Descriptor(x) => Ok(x),
-            Value::Miniscript(x) => Ok(Descriptor::Wsh(x)),
-            Value::Policy(x) => Ok(Descriptor::Wsh(x.compile()?)),
-            Value::PubKey(x) => Ok(Descriptor::Wpkh(x)),
+            Value::Miniscript(x) => {
+                check_top_level(&x)?;
+                Ok(Descriptor::new_wsh(x)?)
+            }
+            Value::SortedMulti(x) => Ok(Descriptor::new_wsh_sortedmulti(x.k, x.pks)?),
+            Value::Policy(x) => Ok(Descriptor::new_wsh(x.compile()?)?),
+            Value::PubKey(x) => Ok(Descriptor::new_wpkh(x)?),
             v => Err(Error::NotDescriptorLike(v)),
         }
     }
@@ -348,6 +610,12 @@ impl Value {
     pub fn into_usize(self) -> Result<usize> {
         self.try_into()
     }
+    pub fn into_bool(self) -> Result<bool> {
+        self.try_into()
+    }
+    pub fn into_string(self) -> Result<String> {
+        self.try_into()
+    }
     pub fn into_key(self) -> Result<DescriptorPublicKey> {
         self.try_into()
     }
@@ -358,12 +626,46 @@ impl Value {
         self.try_into()
     }
     pub fn into_script_pubkey(self) -> Result<Script> {
-        let ctx = get_descriptor_ctx(0);
-        Ok(self.into_desc()?.script_pubkey(ctx))
+        Ok(derive_descriptor(&self.into_desc()?, 0)?.script_pubkey())
     }
     pub fn into_array_elements(self) -> Result<Vec<Value>> {
         Ok(Array::try_from(self)?.0)
     }
+
+    /// The keys used by a policy, miniscript or descriptor, deduplicated
+    pub fn keys(&self) -> Result<Vec<DescriptorPublicKey>> {
+        Ok(analyze::conditions(self.clone())?
+            .into_iter()
+            .filter_map(|condition| match condition {
+                Policy::Key(pk) => Some(pk),
+                _ => None,
+            })
+            .collect())
+    }
+    /// The hashes used by a policy, miniscript or descriptor, deduplicated
+    pub fn hashes(&self) -> Result<Vec<Vec<u8>>> {
+        let mut hashes = vec![];
+        for condition in analyze::conditions(self.clone())? {
+            let hash = match condition {
+                Policy::Sha256(h) => h.into_inner().to_vec(),
+                Policy::Hash256(h) => h.into_inner().to_vec(),
+                Policy::Ripemd160(h) => h.into_inner().to_vec(),
+                Policy::Hash160(h) => h.into_inner().to_vec(),
+                _ => continue,
+            };
+            if !hashes.contains(&hash) {
+                hashes.push(hash);
+            }
+        }
+        Ok(hashes)
+    }
+    /// The `older()` and `after()` timelocks used by a policy, miniscript or descriptor, deduplicated
+    pub fn timelocks(&self) -> Result<Vec<Policy>> {
+        Ok(analyze::conditions(self.clone())?
+            .into_iter()
+            .filter(|condition| matches!(condition, Policy::Older(_) | Policy::After(_)))
+            .collect())
+    }
 }
 
 impl fmt::Display for Value {
@@ -371,12 +673,15 @@ impl fmt::Display for Value {
         match self {
             Value::PubKey(x) => write!(f, "{}", x),
             Value::Number(x) => write!(f, "{}", x),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::String(x) => fmt_quoted(f, x),
             Value::DateTime(x) => write!(f, "{}", x),
             Value::Duration(x) => write!(f, "{:?}", x),
             Value::Hash(x) => write!(f, "{}", x.to_hex()),
             Value::Policy(x) => write!(f, "{}", x),
             Value::WithProb(p, x) => write!(f, "{}@{}", p, x),
             Value::Miniscript(x) => write!(f, "{}", x),
+            Value::SortedMulti(x) => write!(f, "{}", x),
             Value::Descriptor(x) => write!(f, "{}", x),
             Value::Address(x) => write!(f, "{}", x),
             Value::Script(x) => write!(f, "{}", x.to_hex()),
@@ -395,3 +700,21 @@ impl fmt::Display for Value {
         }
     }
 }
+
+// Format as a quoted string literal, escaped such that it can be parsed back
+fn fmt_quoted(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '$' if chars.peek() == Some(&'{') => write!(f, "\\$")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
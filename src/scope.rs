@@ -1,46 +1,287 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, RwLock, Weak};
 
 use crate::ast::Ident;
 use crate::builtins::attach_builtins;
 use crate::error::{Error, Result};
+use crate::function::{Function, NativeFunction};
+use crate::import::ImportResolver;
 use crate::runtime::Value;
 
-#[derive(Default, Debug)]
-pub struct Scope<'a> {
-    parent: Option<&'a Scope<'a>>,
-    local: HashMap<Ident, Value>,
+/// A lexical environment holding variable bindings, with a reference-counted link to its parent.
+///
+/// Cloning a `Scope` is cheap and returns a handle to the same environment. The scopes created while
+/// evaluating a program are owned by its [`Program`], such that user functions can reference the scope
+/// they were defined in weakly, without forming reference cycles with the scopes they are stored in.
+#[derive(Default, Clone)]
+pub struct Scope(Arc<ScopeInner>);
+
+#[derive(Default)]
+pub(crate) struct ScopeInner {
+    parent: Option<Scope>,
+    local: RwLock<HashMap<Ident, Value>>,
+    /// Used to load imported modules, set on the root scope
+    resolver: Option<Arc<dyn ImportResolver>>,
+    /// Set on the top-level scope of imported modules
+    module: Option<Module>,
+    /// The names brought into this scope by `import`, which are not re-exported by modules
+    imported: RwLock<HashSet<Ident>>,
+    /// The `import` statements executed in this scope, by path and namespace alias
+    imports: RwLock<HashSet<(String, Option<Ident>)>>,
+    /// The program this scope was created by, if any
+    program: Weak<Program>,
+    /// The number of nested function calls this scope was created within
+    call_depth: usize,
+}
+
+/// The maximum number of nested function calls, to avoid exhausting the stack on deep recursion
+pub const MAX_CALL_DEPTH: usize = 64;
+
+/// The state of a running program: the scopes created during its evaluation and the modules it loaded.
+///
+/// Scopes are kept alive until the program completes and its `Program` is dropped, after which the
+/// functions defined within it can no longer be called.
+#[derive(Default)]
+pub(crate) struct Program {
+    scopes: RwLock<Vec<Scope>>,
+    /// The modules loaded by the program, keyed by path
+    loaded_modules: RwLock<HashMap<String, Scope>>,
+}
+
+/// A handle to the scope a user function was defined in, owned by the program that created it
+#[derive(Clone)]
+pub(crate) struct CapturedScope(Weak<ScopeInner>);
+
+/// An imported module, used to report errors relative to its source code and to detect circular imports
+pub(crate) struct Module {
+    pub path: String,
+    pub code: String,
+    /// The id of the module's source code, set on the spans parsed from it
+    pub source: usize,
+    importer: Weak<ScopeInner>,
 }
 
-impl<'a> Scope<'a> {
+impl Scope {
     pub fn root() -> Self {
-        let mut scope = Self::default();
-        attach_builtins(&mut scope);
+        let scope = Self::default();
+        attach_builtins(&scope);
         scope
     }
 
-    pub fn get(&self, key: &Ident) -> Option<&Value> {
-        self.local
+    /// Create a root scope that can `import` modules using the given resolver
+    pub fn root_with_resolver(resolver: Arc<dyn ImportResolver>) -> Self {
+        let scope = Scope(Arc::new(ScopeInner {
+            resolver: Some(resolver),
+            ..Default::default()
+        }));
+        attach_builtins(&scope);
+        scope
+    }
+
+    pub fn get(&self, key: &Ident) -> Option<Value> {
+        let local = self.0.local.read().unwrap();
+        local
             .get(key)
-            .or_else(|| self.parent.as_ref().and_then(|p| p.get(key)))
+            .cloned()
+            .or_else(|| self.0.parent.as_ref().and_then(|p| p.get(key)))
     }
 
-    pub fn set<T: Into<Ident>>(&mut self, key: T, value: Value) -> Result<()> {
+    pub fn set<T: Into<Ident>>(&self, key: T, value: Value) -> Result<()> {
         let key = key.into();
+        let mut local = self.0.local.write().unwrap();
 
         #[allow(clippy::map_entry)]
-        if self.local.contains_key(&key) {
+        if local.contains_key(&key) {
             // cannot be set if already exists in this scope, but could shadow over a definition from a parent scope
             Err(Error::AssignedVariableExists(key))
         } else {
-            self.local.insert(key, value);
+            local.insert(key, value);
             Ok(())
         }
     }
 
-    pub fn child(&'a self) -> Self {
-        Scope {
-            parent: Some(&self),
-            local: HashMap::new(),
+    /// Set a variable imported from a module
+    pub(crate) fn set_imported(&self, key: Ident, value: Value) -> Result<()> {
+        self.set(key.clone(), value)?;
+        self.0.imported.write().unwrap().insert(key);
+        Ok(())
+    }
+
+    /// Record an `import` of the module at `path` into this scope, which may be imported at most once
+    /// per namespace alias
+    pub(crate) fn add_import(&self, path: &str, alias: Option<&Ident>) -> Result<()> {
+        let mut imports = self.0.imports.write().unwrap();
+        let is_new = imports.insert((path.into(), alias.cloned()));
+        ensure!(is_new, Error::DuplicateImport(path.into()));
+        Ok(())
+    }
+
+    /// Register a native Rust function or closure, callable from Minsc code as `ident(...)`
+    pub fn set_fn<T, F>(&self, ident: T, body: F) -> Result<()>
+    where
+        T: Into<Ident>,
+        F: Fn(Vec<Value>, &Scope) -> Result<Value> + Send + Sync + 'static,
+    {
+        let ident = ident.into();
+        let func = Function::from(NativeFunction::new(ident.clone(), body));
+        self.set(ident, func.into())
+    }
+
+    pub fn child(&self) -> Self {
+        self.register(Scope(Arc::new(ScopeInner {
+            parent: Some(self.clone()),
+            program: self.0.program.clone(),
+            call_depth: self.0.call_depth,
+            ..Default::default()
+        })))
+    }
+
+    /// Create the scope for the body of a function defined in this scope, called from `caller`
+    pub(crate) fn call_child(&self, caller: &Scope) -> Result<Self> {
+        let call_depth = caller.0.call_depth + 1;
+        ensure!(
+            call_depth <= MAX_CALL_DEPTH,
+            Error::MaxRecursionDepth(MAX_CALL_DEPTH)
+        );
+        Ok(self.register(Scope(Arc::new(ScopeInner {
+            parent: Some(self.clone()),
+            program: self.0.program.clone(),
+            call_depth,
+            ..Default::default()
+        }))))
+    }
+
+    /// Create the top-level scope of a new program, owned by the returned `Program`
+    pub(crate) fn program_child(&self) -> (Self, Arc<Program>) {
+        let program = Arc::new(Program::default());
+        let scope = self.register(Scope(Arc::new(ScopeInner {
+            parent: Some(self.clone()),
+            program: Arc::downgrade(&program),
+            call_depth: self.0.call_depth,
+            ..Default::default()
+        })));
+        (scope, program)
+    }
+
+    /// Create the scope for a module imported by this scope. Modules are evaluated as a child of
+    /// the root scope, without access to the importer's variables.
+    pub(crate) fn module_child(&self, path: &str, code: String, source: usize) -> Self {
+        let module = Module {
+            path: path.into(),
+            code,
+            source,
+            importer: Arc::downgrade(&self.0),
+        };
+        self.register(Scope(Arc::new(ScopeInner {
+            parent: Some(self.root_scope()),
+            module: Some(module),
+            program: self.0.program.clone(),
+            call_depth: self.0.call_depth,
+            ..Default::default()
+        })))
+    }
+
+    // Hand the ownership of a newly created scope over to its program
+    fn register(&self, scope: Scope) -> Scope {
+        if let Some(program) = scope.0.program.upgrade() {
+            program.scopes.write().unwrap().push(scope.clone());
+        }
+        scope
+    }
+
+    /// Check whether this scope belongs to a running program
+    pub(crate) fn in_program(&self) -> bool {
+        self.0.program.strong_count() > 0
+    }
+
+    /// Get a handle to this scope for the functions defined within it
+    pub(crate) fn capture(&self) -> CapturedScope {
+        CapturedScope(Arc::downgrade(&self.0))
+    }
+
+    pub(crate) fn root_scope(&self) -> Scope {
+        match &self.0.parent {
+            Some(parent) => parent.root_scope(),
+            None => self.clone(),
+        }
+    }
+
+    pub(crate) fn resolver(&self) -> Option<Arc<dyn ImportResolver>> {
+        self.root_scope().0.resolver.clone()
+    }
+
+    /// Get the module this scope belongs to, or None for the main program
+    pub(crate) fn module(&self) -> Option<&Module> {
+        self.0
+            .module
+            .as_ref()
+            .or_else(|| self.0.parent.as_ref()?.module())
+    }
+
+    /// Check whether `path` is currently being imported, by this scope's module or the modules that imported it
+    pub(crate) fn is_importing(&self, path: &str) -> bool {
+        match self.module() {
+            Some(module) if module.path == path => true,
+            Some(module) => match module.importer.upgrade() {
+                Some(importer) => Scope(importer).is_importing(path),
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Get the variables defined directly within this scope, excluding the ones it imported itself,
+    /// sorted by name
+    pub(crate) fn exports(&self) -> Vec<(Ident, Value)> {
+        let local = self.0.local.read().unwrap();
+        let imported = self.0.imported.read().unwrap();
+        let mut exports: Vec<_> = local
+            .iter()
+            .filter(|(k, _)| !imported.contains(k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        exports.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        exports
+    }
+
+    /// Get the module at `path` if it was already loaded by the program
+    pub(crate) fn loaded_module(&self, path: &str) -> Option<Scope> {
+        let program = self.0.program.upgrade()?;
+        let loaded_modules = program.loaded_modules.read().unwrap();
+        loaded_modules.get(path).cloned()
+    }
+
+    pub(crate) fn add_loaded_module(&self, path: &str, module: Scope) {
+        if let Some(program) = self.0.program.upgrade() {
+            let mut loaded_modules = program.loaded_modules.write().unwrap();
+            loaded_modules.insert(path.into(), module);
+        }
+    }
+}
+
+impl CapturedScope {
+    /// Get the scope, which is only available for as long as the program that created it is running
+    pub fn get(&self) -> Result<Scope> {
+        self.0.upgrade().map(Scope).ok_or(Error::ProgramEnded)
+    }
+}
+
+impl fmt::Debug for CapturedScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.upgrade() {
+            Some(scope) => Scope(scope).fmt(f),
+            None => write!(f, "Scope(<ended>)"),
         }
     }
 }
+
+// Scopes may be referenced by the functions defined within them, so we avoid printing the values
+impl fmt::Debug for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let local = self.0.local.read().unwrap();
+        let mut keys: Vec<_> = local.keys().map(|k| &k.0).collect();
+        keys.sort();
+        f.debug_struct("Scope").field("local", &keys).finish()
+    }
+}
@@ -0,0 +1,86 @@
+//! Construction of taproot trees from policies
+
+use miniscript::descriptor::DescriptorPublicKey;
+
+use crate::Policy;
+
+/// The BIP341 NUMS point, which has no known private key. Used as the internal key of policies that
+/// have no key-only branch, leaving the key path unspendable.
+pub const UNSPENDABLE_KEY: &str =
+    "0250929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+pub fn unspendable_key() -> DescriptorPublicKey {
+    UNSPENDABLE_KEY.parse().expect("valid public key")
+}
+
+/// A taproot script tree with uncompiled policy leaves
+#[derive(Debug, Clone)]
+pub enum PolicyTree {
+    Leaf(Policy),
+    Branch(Box<PolicyTree>, Box<PolicyTree>),
+}
+
+/// Split a policy into the internal key and script tree of a taproot output. The most likely key-only
+/// branch of the top-level `or`s becomes the internal key, and the other branches are arranged in a
+/// Huffman tree weighted by their probabilities, placing the more likely ones closer to the root.
+pub fn split(policy: &Policy) -> (DescriptorPublicKey, Option<PolicyTree>) {
+    let mut branches = vec![];
+    collect_branches(policy, 1.0, &mut branches);
+
+    // Ties are broken in favour of the first key
+    let key_branch = branches
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, branch))| matches!(branch, Policy::Key(_)))
+        .fold(
+            None,
+            |best: Option<(usize, f64)>, (index, (prob, _))| match best {
+                Some((_, best_prob)) if best_prob >= *prob => best,
+                _ => Some((index, *prob)),
+            },
+        )
+        .map(|(index, _)| index);
+    let internal_key = match key_branch.map(|index| branches.remove(index).1) {
+        Some(Policy::Key(pk)) => pk,
+        _ => unspendable_key(),
+    };
+
+    (internal_key, huffman_tree(branches))
+}
+
+// Flatten nested `or`s into their branches, with the probability of reaching each one. Thresholds of
+// 1, as produced by `||` with more than two policies, are `or`s with equally likely branches.
+fn collect_branches(policy: &Policy, prob: f64, branches: &mut Vec<(f64, Policy)>) {
+    match policy {
+        Policy::Or(subs) => {
+            let total: usize = subs.iter().map(|(weight, _)| weight).sum();
+            for (weight, sub) in subs {
+                collect_branches(sub, prob * *weight as f64 / total as f64, branches);
+            }
+        }
+        Policy::Threshold(1, subs) => {
+            for sub in subs {
+                collect_branches(sub, prob / subs.len() as f64, branches);
+            }
+        }
+        _ => branches.push((prob, policy.clone())),
+    }
+}
+
+// Repeatedly join the two least likely nodes under a new branch. Ties are broken in favour of the
+// original order, with joined nodes taking precedence over the ones that are yet to be joined.
+fn huffman_tree(branches: Vec<(f64, Policy)>) -> Option<PolicyTree> {
+    let mut nodes: Vec<_> = branches
+        .into_iter()
+        .map(|(prob, policy)| (prob, PolicyTree::Leaf(policy)))
+        .collect();
+    while nodes.len() > 1 {
+        // Sorted by descending probability, so that the least likely nodes are at the end
+        nodes.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        let (right_prob, right) = nodes.pop().unwrap();
+        let (left_prob, left) = nodes.pop().unwrap();
+        let branch = PolicyTree::Branch(Box::new(left), Box::new(right));
+        nodes.insert(0, (left_prob + right_prob, branch));
+    }
+    nodes.pop().map(|(_, tree)| tree)
+}
@@ -1,3 +1,5 @@
+use std::convert::TryFrom;
+
 use chrono::{NaiveDate, NaiveDateTime};
 
 use crate::ast::{Duration, DurationPart};
@@ -22,6 +24,16 @@ pub fn duration_to_seq(duration: &Duration) -> Result<u32> {
     }
 }
 
+/// Check whether a relative timelock sequence value is by-blocktime (as opposed to by-blockheight)
+pub fn is_blocktime_seq(seq: u32) -> bool {
+    seq & SEQUENCE_LOCKTIME_TYPE_FLAG != 0
+}
+
+/// Check whether an absolute timelock is by-blocktime (as opposed to by-blockheight)
+pub fn is_blocktime_locktime(locktime: u32) -> bool {
+    locktime >= LOCKTIME_THRESHOLD
+}
+
 fn rel_height_to_seq(num_blocks: u32) -> Result<u32> {
     ensure!(
         num_blocks > 0 && num_blocks <= BLOCKS_MAX,
@@ -58,6 +70,28 @@ fn rel_time_to_seq(parts: &[DurationPart], heightwise: bool) -> Result<u32> {
     Ok(SEQUENCE_LOCKTIME_TYPE_FLAG | units)
 }
 
+/// Validate a raw number as the sequence value for a relative timelock
+pub fn number_to_seq(num: usize) -> Result<u32> {
+    let seq = u32::try_from(num).map_err(|_| Error::InvalidRelLockTimeOutOfRange(num))?;
+    ensure!(
+        seq & !(SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK) == 0,
+        Error::InvalidRelLockTimeBits(seq)
+    );
+    ensure!(
+        seq & SEQUENCE_LOCKTIME_MASK > 0,
+        Error::InvalidRelLockTimeOutOfRange(num)
+    );
+    Ok(seq)
+}
+
+/// Validate a raw number as an absolute timelock (block height or unix timestamp)
+pub fn number_to_locktime(num: usize) -> Result<u32> {
+    match u32::try_from(num) {
+        Ok(locktime) if locktime > 0 => Ok(locktime),
+        _ => Err(Error::InvalidAbsLockTimeOutOfRange(num)),
+    }
+}
+
 pub fn parse_datetime(s: &str) -> Result<u32> {
     let ts = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M")
         .or_else(|_| Ok::<_, Error>(NaiveDate::parse_from_str(s, "%Y-%m-%d")?.and_hms(0, 0, 0)))?
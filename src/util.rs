@@ -1,17 +1,28 @@
-use std::fmt::Debug;
 use std::str::FromStr;
 
-use miniscript::{bitcoin::secp256k1, descriptor};
+use miniscript::bitcoin::{secp256k1, PublicKey};
+use miniscript::descriptor;
+
+use crate::ast::{self, Expr, ExprKind, Span};
+use crate::error::{Error, Result};
+use crate::grammar::ExprParser;
 
 lazy_static! {
     static ref EC: secp256k1::Secp256k1<secp256k1::VerifyOnly> =
         secp256k1::Secp256k1::verification_only();
 }
 
-pub fn get_descriptor_ctx(
+/// Derive a key into a concrete public key, using `child_code` for wildcard keys
+pub fn derive_key(pk: &descriptor::DescriptorPublicKey, child_code: u32) -> Result<PublicKey> {
+    Ok(pk.clone().derive(child_code).derive_public_key(&EC)?)
+}
+
+/// Derive the descriptor keys into concrete public keys, using `child_code` for wildcard keys
+pub fn derive_descriptor(
+    desc: &crate::Descriptor,
     child_code: u32,
-) -> descriptor::DescriptorPublicKeyCtx<'static, secp256k1::VerifyOnly> {
-    descriptor::DescriptorPublicKeyCtx::new(&EC, child_code.into())
+) -> Result<descriptor::Descriptor<PublicKey>> {
+    Ok(desc.derived_descriptor(&EC, child_code)?)
 }
 
 pub fn concat<T>(mut list: Vec<T>, val: Option<T>) -> Vec<T> {
@@ -22,10 +33,98 @@ pub fn concat<T>(mut list: Vec<T>, val: Option<T>) -> Vec<T> {
 }
 
 // extract N out of "N years"
-// `s` is assumed to be valid, because the parser already matched it against a regex
-pub fn parse_str_prefix<T: FromStr>(s: &str) -> T
-where
-    <T as FromStr>::Err: Debug,
-{
-    s.split_ascii_whitespace().next().unwrap().parse().unwrap()
+// `s` is assumed to be well-formed, because the parser already matched it against a regex
+pub fn parse_str_prefix<T: FromStr>(s: &str, offset: usize) -> Result<T> {
+    parse_number(s.split_ascii_whitespace().next().unwrap(), offset)
+}
+
+/// Parse a numeric literal found at `offset` within the source code, which may be too large for `T`
+pub fn parse_number<T: FromStr>(s: &str, offset: usize) -> Result<T> {
+    s.parse()
+        .map_err(|_| Error::NumberOutOfRange(s.into()).at(Span::new(offset, offset + s.len())))
+}
+
+/// Parse a quoted string literal token into a String expression, or into an Interpolation if it
+/// contains `${..}` expressions. `offset` is the position of the token within the source code,
+/// used for reporting the correct location of errors within the string.
+pub fn parse_string_literal(s: &str, offset: usize) -> Result<ExprKind> {
+    let inner = &s[1..s.len() - 1];
+    let offset = offset + 1;
+
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut current_start = 0;
+    let mut chars = inner.char_indices().peekable();
+
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '\\' => {
+                let (_, escaped) = chars.next().unwrap(); // the lexer guarantees a following char
+                current.push(match escaped {
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    '\\' | '"' | '$' => escaped,
+                    _ => bail!(Error::InvalidEscape(escaped).at(Span::new(
+                        offset + pos,
+                        offset + pos + 1 + escaped.len_utf8()
+                    ))),
+                });
+            }
+            '$' if matches!(chars.peek(), Some((_, '{'))) => {
+                chars.next();
+                let expr_start = pos + 2;
+                let mut depth = 1;
+                let expr_end = loop {
+                    match chars.next() {
+                        Some((_, '{')) => depth += 1,
+                        Some((end, '}')) if depth == 1 => break end,
+                        Some((_, '}')) => depth -= 1,
+                        Some(_) => (),
+                        None => bail!(Error::UnterminatedInterpolation
+                            .at(Span::new(offset + pos, offset + inner.len()))),
+                    }
+                };
+                if !current.is_empty() {
+                    let span = Span::new(offset + current_start, offset + pos);
+                    parts.push(Expr {
+                        kind: ExprKind::String(std::mem::take(&mut current)),
+                        span,
+                    });
+                }
+                parts.push(parse_interpolation(
+                    &inner[expr_start..expr_end],
+                    offset + expr_start,
+                )?);
+                current_start = expr_end + 1;
+            }
+            c => current.push(c),
+        }
+    }
+
+    if parts.is_empty() {
+        return Ok(ExprKind::String(current));
+    }
+    if !current.is_empty() {
+        let span = Span::new(offset + current_start, offset + inner.len());
+        parts.push(Expr {
+            kind: ExprKind::String(current),
+            span,
+        });
+    }
+    Ok(ast::Interpolation(parts).into())
+}
+
+// The spans of the parsed expression (and the locations of parse errors) are shifted by the
+// expression's offset, so that they match the full source code
+fn parse_interpolation(code: &str, offset: usize) -> Result<Expr> {
+    let mut shift = |span: &mut Span| {
+        span.start += offset;
+        span.end += offset;
+    };
+    let mut expr = ExprParser::new()
+        .parse(code)
+        .map_err(|e| Error::from(e).map_spans(&mut shift))?;
+    expr.for_each_span(&mut shift);
+    Ok(expr)
 }
@@ -1,5 +1,7 @@
+use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
+use crate::import::MemoryResolver;
 use crate::{parse, Evaluate, Result, Scope, Value};
 
 #[cfg(feature = "wee_alloc")]
@@ -8,12 +10,20 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 #[wasm_bindgen(js_name = run)]
 pub fn js_run(code: &str) -> std::result::Result<JsValue, JsValue> {
-    let value = run(code).map_err(|e| e.to_string())?;
+    let value = run(code).map_err(|e| e.format_with_source(code, "<input>"))?;
     Ok(JsValue::from_str(&value.to_string()))
 }
 
+/// Register a module that can be imported from Minsc code using `import "<path>";`
+#[wasm_bindgen(js_name = registerModule)]
+pub fn js_register_module(path: &str, code: &str) {
+    MODULES.insert(path, code);
+}
+
 lazy_static! {
-    static ref ROOT_SCOPE: Scope<'static> = Scope::root();
+    // Imports are resolved from the in-memory modules registered by the JavaScript side
+    pub(crate) static ref MODULES: Arc<MemoryResolver> = Arc::new(MemoryResolver::new());
+    static ref ROOT_SCOPE: Scope = Scope::root_with_resolver(MODULES.clone());
 }
 
 fn run(code: &str) -> Result<Value> {
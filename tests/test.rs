@@ -1,4 +1,9 @@
-use minsc::run;
+use minsc::analyze;
+use minsc::checksum::with_checksum;
+use minsc::function::Call;
+use minsc::import::MemoryResolver;
+use minsc::{parse, run, Evaluate, Policy, Scope, Span, Value};
+use std::sync::Arc;
 
 fn test(minsc: &str, expected_policy: &str) {
     let res = run(&replace_dummy(minsc)).unwrap();
@@ -6,6 +11,15 @@ fn test(minsc: &str, expected_policy: &str) {
     assert_eq!(policy, replace_dummy(expected_policy));
 }
 
+// Descriptors are displayed with their checksum
+fn test_desc(minsc: &str, expected_desc: &str) {
+    let res = run(&replace_dummy(minsc)).unwrap();
+    assert_eq!(
+        res.to_string(),
+        with_checksum(&replace_dummy(expected_desc))
+    );
+}
+
 #[test]
 fn test_policy_is_valid_minsc() {
     test("pk(A)", "pk(A)");
@@ -75,6 +89,1197 @@ fn test_functions() {
     );
 }
 
+#[test]
+fn test_lexical_scope() {
+    // functions see the variables from where they were defined, not where they were called
+    test(
+        r"
+        $key = pk(A);
+        fn f() = $key;
+        fn g($key) = f();
+        g(pk(B))
+        ",
+        "pk(A)",
+    );
+
+    // functions returned from a block capture their defining scope
+    test(
+        r"
+        fn with_key($a) {
+          fn inner($b) = pk($a) && pk($b);
+          inner
+        }
+        $with_a = with_key(A);
+        $with_a(B)
+        ",
+        "and(pk(A),pk(B))",
+    );
+
+    // functions can reference definitions that come after them
+    test(
+        r"
+        fn main() = two_keys(A, B);
+        fn two_keys($a, $b) = pk($a) && pk($b);
+        ",
+        "and(pk(A),pk(B))",
+    );
+}
+
+#[test]
+fn test_anonymous_functions() {
+    test(
+        r"
+        $both = |$a, $b| pk($a) && pk($b);
+        $both(A, B)
+        ",
+        "and(pk(A),pk(B))",
+    );
+    test(
+        r"
+        fn with_timeout($f, $key) = $f($key) || older(10);
+        with_timeout(|$k| pk($k) && pk(C), A)
+        ",
+        "or(1@and(pk(A),pk(C)),1@older(10))",
+    );
+    test("$f = || pk(A); $f()", "pk(A)");
+}
+
+#[test]
+fn test_array_functions() {
+    test("all(map([A, B], pk))", "thresh(2,pk(A),pk(B))");
+    test(
+        "any(map([A, B], |$k| pk($k) && older(10)))",
+        "thresh(1,and(pk(A),older(10)),and(pk(B),older(10)))",
+    );
+    test(
+        "all(flat_map([A, B], |$k| [ pk($k), sha256(H) ]))",
+        "thresh(4,pk(A),sha256(H),pk(B),sha256(H))",
+    );
+    test(
+        "reduce(map([A, B, C], pk), |$a, $b| $a || $b)",
+        "or(1@or(1@pk(A),1@pk(B)),1@pk(C))",
+    );
+    test(
+        "fold([A, B], pk(C), |$acc, $k| $acc && pk($k))",
+        "and(and(pk(C),pk(A)),pk(B))",
+    );
+    test(
+        "all(map(zip([A, B], [C, D, E]), |$p| pk($p.0) || pk($p.1)))",
+        "thresh(2,or(1@pk(A),1@pk(C)),or(1@pk(B),1@pk(D)))",
+    );
+    test(
+        "all(map(filter(zip([A, B, C], [true, false, true]), |$p| $p.1), |$p| pk($p.0)))",
+        "thresh(2,pk(A),pk(C))",
+    );
+}
+
+#[test]
+fn test_native_closures() {
+    // a host-provided key lookup, backed by state captured in the closure
+    let wallet_keys: Vec<Value> = vec![replace_dummy("A"), replace_dummy("B")]
+        .into_iter()
+        .map(|k| Value::PubKey(k.parse().unwrap()))
+        .collect();
+    let scope = Scope::root();
+    scope
+        .set_fn("wallet_key", move |mut args, _| {
+            let index = args.remove(0).into_usize()?;
+            Ok(wallet_keys[index].clone())
+        })
+        .unwrap();
+
+    let res = parse("pk(wallet_key(1)) || likely@pk(wallet_key(0))")
+        .unwrap()
+        .eval(&scope)
+        .unwrap();
+    assert_eq!(
+        res.into_policy().unwrap().to_string(),
+        replace_dummy("or(1@pk(B),10@pk(A))")
+    );
+}
+
+#[test]
+fn test_scopes_are_freed() {
+    // scopes and the functions defined in them must not keep each other alive. the marker is held by
+    // a native function in the root scope, which is only freed once all of its child scopes are
+    let marker = Arc::new(());
+    let scope = Scope::root();
+    let captured = marker.clone();
+    scope
+        .set_fn(
+            "marker",
+            move |_, _| Ok(Arc::strong_count(&captured).into()),
+        )
+        .unwrap();
+    let res = parse(&replace_dummy(
+        r"
+        fn with_key($a) {
+          fn inner($b) = pk($a) && pk($b);
+          inner
+        }
+        $with_a = with_key(A);
+        $both = |$k| $with_a($k) && pk(C);
+        $fns = [ $both, |$k| pk($k) ];
+        $first = $fns.0;
+        $escaped = { $c = pk(C); |$k| $first($k) || $c };
+        $escaped(B)
+        ",
+    ))
+    .unwrap()
+    .eval(&scope)
+    .unwrap();
+    assert_eq!(
+        res.into_policy().unwrap().to_string(),
+        replace_dummy("or(1@and(and(pk(A),pk(B)),pk(C)),1@pk(C))")
+    );
+    drop(scope);
+    assert_eq!(Arc::strong_count(&marker), 1);
+
+    // functions that outlive their program can no longer be called
+    let func = run("fn f($k) = pk($k); f").unwrap();
+    let err = func
+        .call(vec![Value::Number(1)], &Scope::root())
+        .unwrap_err();
+    assert!(matches!(err, minsc::Error::ProgramEnded));
+}
+
+#[test]
+fn test_conditionals() {
+    test("if 3 > 2 { pk(A) } else { pk(B) }", "pk(A)");
+    test("if 1 day >= 2 days { pk(A) } else { pk(B) }", "pk(B)");
+    test(
+        r"
+        fn timeout($n) = if $n == 0 { pk(A) } else if $n < 10 { pk(A) && older($n) } else { pk(B) };
+        [ timeout(0), timeout(5), timeout(10) ]
+        ",
+        "thresh(3,pk(A),and(pk(A),older(5)),pk(B))",
+    );
+    test(
+        "if 2030-01-01 > 2020-01-01 and !(A == B) { pk(A) } else { pk(B) }",
+        "pk(A)",
+    );
+    // the boolean operators short-circuit, the undefined $x is never evaluated
+    test("if true or $x { pk(A) } else { pk(B) }", "pk(A)");
+    test("if false and $x { pk(A) } else { pk(B) }", "pk(B)");
+
+    assert!(run("if 1 { pk(A) } else { pk(B) }").is_err());
+    assert!(run("1 day < 10 blocks").is_err());
+}
+
+#[test]
+fn test_call_depth() {
+    // Run on a thread with a stack as large as the main thread's, which unoptimized builds need
+    // to reach the maximum call depth
+    let thread = std::thread::Builder::new().stack_size(8 * 1024 * 1024);
+    let handle = thread.spawn(|| {
+        let countdown = "fn f($n) = if $n == 0 { pk(A) } else { f($n - 1) };";
+        test(&format!("{} f(50)", countdown), "pk(A)");
+
+        let err = run(&format!("{} f(100)", countdown)).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Maximum call depth of 64 exceeded"));
+    });
+    handle.unwrap().join().unwrap();
+}
+
+#[test]
+fn test_arithmetic() {
+    test(
+        r"
+        $signers = [ pk(A), pk(B), pk(C), pk(D), pk(E) ];
+        $n = 5;
+        ($n / 2 + 1) of $signers
+        ",
+        "thresh(3,pk(A),pk(B),pk(C),pk(D),pk(E))",
+    );
+    test("older(2 + 3 * 4 - 10 % 4)", "older(12)");
+    test("older((2 + 3) * 4)", "older(20)");
+    test("older(100 / 5 / 2)", "older(10)");
+    test("older(10 * 6 / 4)", "older(15)");
+    test("if 7 % 2 == 1 { pk(A) } else { pk(B) }", "pk(A)");
+
+    let err_msg = |code| run(code).unwrap_err().to_string();
+    assert_eq!(err_msg("older(1 / 0)"), "Division by zero");
+    assert_eq!(err_msg("older(1 % 0)"), "Division by zero");
+    assert_eq!(err_msg("older(1 - 2)"), "Integer overflow");
+    assert_eq!(
+        err_msg("older(18446744073709551615 + 1)"),
+        "Integer overflow"
+    );
+}
+
+#[test]
+fn test_numeric_ranges() {
+    let err_msg = |code| run(code).unwrap_err().to_string();
+
+    // relative timelocks, validated against the BIP68 layout
+    test("older(1)", "older(1)");
+    test("older(65535)", "older(65535)");
+    test("older(4194305)", "older(4194305)");
+    test("older(4259839)", "older(4259839)");
+    test("older(65535 blocks)", "older(65535)");
+    assert_eq!(
+        err_msg("older(0)"),
+        "in older(): Relative timelock out of range: 0 (expected a BIP68 sequence number with a value between 1 and 65535, optionally with the type flag (1<<22) set)"
+    );
+    assert_eq!(
+        err_msg("older(4194304)"),
+        "in older(): Relative timelock out of range: 4194304 (expected a BIP68 sequence number with a value between 1 and 65535, optionally with the type flag (1<<22) set)"
+    );
+    assert_eq!(
+        err_msg("older(4294967297)"),
+        "in older(): Relative timelock out of range: 4294967297 (expected a BIP68 sequence number with a value between 1 and 65535, optionally with the type flag (1<<22) set)"
+    );
+    assert_eq!(
+        err_msg("older(65536)"),
+        "in older(): Invalid relative timelock: 0x10000 has bits set outside of the BIP68 type flag (1<<22) and 16-bit value"
+    );
+    assert_eq!(
+        err_msg("older(2147483649)"),
+        "in older(): Invalid relative timelock: 0x80000001 has bits set outside of the BIP68 type flag (1<<22) and 16-bit value"
+    );
+    assert!(run("older(65536 blocks)").is_err());
+
+    // absolute timelocks
+    test("after(1)", "after(1)");
+    test("after(4294967295)", "after(4294967295)");
+    assert_eq!(
+        err_msg("after(0)"),
+        "in after(): Absolute timelock out of range: 0 (expected a number between 1 and 4294967295)"
+    );
+    assert_eq!(
+        err_msg("after(4294967296)"),
+        "in after(): Absolute timelock out of range: 4294967296 (expected a number between 1 and 4294967295)"
+    );
+
+    // unhardened child codes
+    let xpub = "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw";
+    let res = run(&format!("pk({}/2147483647)", xpub)).unwrap();
+    assert_eq!(
+        res.into_policy().unwrap().to_string(),
+        format!("pk({}/2147483647)", xpub)
+    );
+    let code = format!("pk({}/0/2147483648)", xpub);
+    let err = run(&code).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Child code out of range: 2147483648 (unhardened child codes must be below 2^31)"
+    );
+    assert_eq!(err.span().unwrap().line_col(&code), (1, 118));
+
+    // numeric literals too large to be represented
+    let err = parse("1 + 99999999999999999999999").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Number out of range: 99999999999999999999999 (too large to be represented)"
+    );
+    assert_eq!(
+        err.span().unwrap().line_col("1 + 99999999999999999999999"),
+        (1, 5)
+    );
+    assert_eq!(
+        parse("99999999999999999999 blocks")
+            .unwrap_err()
+            .to_string(),
+        "Number out of range: 99999999999999999999 (too large to be represented)"
+    );
+    assert!(parse("older(4294967296 blocks)").is_err());
+}
+
+#[test]
+fn test_strings() {
+    let eval_str = |code| run(code).unwrap().into_string().unwrap();
+    assert_eq!(eval_str(r#""hello""#), "hello");
+    assert_eq!(eval_str(r#""foo" + "bar""#), "foobar");
+    assert_eq!(
+        eval_str(r#""a \"quoted\"\tstring\\ costs \$5 not \${x}""#),
+        "a \"quoted\"\tstring\\ costs $5 not ${x}"
+    );
+    assert_eq!(eval_str(r#"$i = 2; "key-${$i}""#), "key-2");
+    assert_eq!(
+        eval_str(
+            r#"$name = "alice"; $bang = "!"; "${$name}/${$name + $bang}: ${1 + 2 * 3} ${3 > 2}""#
+        ),
+        "alice/alice!: 7 true"
+    );
+    assert_eq!(eval_str(r#""${ { $a = 1; $a } }""#), "1");
+    assert!(run(r#""a" == "a" and "a" < "b" and "a" != "b""#)
+        .unwrap()
+        .into_bool()
+        .unwrap());
+
+    // displayed as a quoted string literal that can be parsed back
+    let s = run(r#""say \"hi\" for \$1\n""#).unwrap().to_string();
+    assert_eq!(s, r#""say \"hi\" for $1\n""#);
+    assert_eq!(
+        run(&s).unwrap().into_string().unwrap(),
+        "say \"hi\" for $1\n"
+    );
+
+    assert!(run(r#""a" + 1"#).is_err());
+    let code = "$x = 1;\n\"foo ${$x +} bar\"";
+    let err = run(code).unwrap_err();
+    assert_eq!(err.span().unwrap().line_col(code), (2, 12));
+    let code = r#""foo \q""#;
+    let err = run(code).unwrap_err();
+    assert_eq!(err.to_string(), r"Invalid escape sequence: \q");
+    assert_eq!(err.span().unwrap().line_col(code), (1, 6));
+    assert!(run(r#""foo ${1""#).is_err());
+    // runtime errors within interpolations are located relative to the full source code
+    let code = "$x = 1;\n\"a ${ { $z = $x; $z + $y } }\"";
+    let err = run(code).unwrap_err();
+    assert_eq!(err.to_string(), "Undefined variable: $y");
+    assert_eq!(err.span().unwrap().line_col(code), (2, 23));
+}
+
+#[test]
+fn test_imports() {
+    let modules = MemoryResolver::new();
+    modules.insert("keys.minsc", replace_dummy("$alice = pk(A); $bob = pk(B);"));
+    modules.insert(
+        "lib/2fa.minsc",
+        r#"
+        import "keys.minsc";
+        fn two_factor($provider, $delay) = $alice && (likely@$provider || older($delay));
+        fn broken() = older(0);
+        "#,
+    );
+    modules.insert("lib/hof.minsc", "fn apply($f, $x) = $f($x);");
+    modules.insert("lib/relative.minsc", r#"import "fee.minsc"; $x = $fee;"#);
+    modules.insert("lib/fee.minsc", "$fee = 1;");
+    modules.insert("fee.minsc", "$fee = 2;");
+    modules.insert("cycle_a.minsc", r#"import "cycle_b.minsc";"#);
+    modules.insert("cycle_b.minsc", r#"import "cycle_a.minsc";"#);
+    modules.insert("leak.minsc", "$x = $secret;");
+    let scope = Scope::root_with_resolver(Arc::new(modules));
+    let eval = |code: &str| parse(&replace_dummy(code))?.eval(&scope);
+
+    let res = eval(r#"import "keys.minsc"; import "lib/2fa.minsc"; two_factor($bob, 10)"#).unwrap();
+    assert_eq!(
+        res.into_policy().unwrap().to_string(),
+        replace_dummy("and(pk(A),or(10@pk(B),1@older(10)))")
+    );
+    // the names a module imports itself are not re-exported
+    assert!(eval(r#"import "lib/2fa.minsc"; $bob"#).is_err());
+
+    // namespaced imports
+    let res = eval(
+        r#"import "lib/2fa.minsc" as tfa; import "keys.minsc" as k; tfa.two_factor(k.$bob, 10)"#,
+    )
+    .unwrap();
+    assert_eq!(
+        res.into_policy().unwrap().to_string(),
+        replace_dummy("and(pk(A),or(10@pk(B),1@older(10)))")
+    );
+    assert!(eval(r#"import "lib/2fa.minsc" as tfa; two_factor($bob, 10)"#).is_err());
+
+    // errors within imported functions are reported relative to the module
+    let err = eval(r#"import "lib/2fa.minsc"; broken()"#).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("in broken(): lib/2fa.minsc:4:23: in older(): Relative timelock"));
+    // but not errors raised by functions from the importer that the module calls
+    let code = "import \"lib/hof.minsc\";\n$s = \"héllo\"; apply(|$n| older($n), 0)";
+    let err = eval(code).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("in apply(): in $f(): in older(): Relative timelock"));
+    assert_eq!(err.span().unwrap().line_col(code), (2, 26));
+
+    let err_msg = |code| eval(code).unwrap_err().to_string();
+    assert_eq!(
+        err_msg(r#"import "cycle_a.minsc"; 1"#),
+        "cycle_a.minsc:1:1: cycle_b.minsc:1:1: Circular import of cycle_a.minsc"
+    );
+    assert_eq!(
+        err_msg(r#"import "missing.minsc"; 1"#),
+        "Module not found: missing.minsc"
+    );
+    // modules cannot see the importer's variables
+    assert_eq!(
+        err_msg(r#"$secret = 1; import "leak.minsc"; 1"#),
+        "leak.minsc:1:6: Undefined variable: $secret"
+    );
+    // conflicting names
+    assert!(eval(r#"$alice = 1; import "keys.minsc"; 1"#).is_err());
+    assert_eq!(
+        err_msg(r#"import "keys.minsc"; import "keys.minsc"; 1"#),
+        "Module imported more than once: keys.minsc"
+    );
+    assert!(eval(r#"import "keys.minsc"; import "keys.minsc" as k; k.$alice"#).is_ok());
+
+    // relative paths are resolved from the importing module's directory first
+    let res = eval(r#"import "lib/relative.minsc"; import "fee.minsc"; $x == 1 and $fee == 2"#);
+    assert!(res.unwrap().into_bool().unwrap());
+    // no resolver
+    assert!(run(r#"import "keys.minsc"; 1"#).is_err());
+}
+
+#[test]
+fn test_imports_loaded_once() {
+    let modules = MemoryResolver::new();
+    modules.insert(
+        "keys.minsc",
+        replace_dummy("$loads = count_load(); $alice = pk(A);"),
+    );
+    modules.insert(
+        "lib.minsc",
+        r#"import "keys.minsc"; fn with_alice($k) = $alice && pk($k);"#,
+    );
+    let scope = Scope::root_with_resolver(Arc::new(modules));
+    let loads = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = loads.clone();
+    scope
+        .set_fn("count_load", move |_, _| {
+            Ok((counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1).into())
+        })
+        .unwrap();
+    let eval = |code: &str| parse(&replace_dummy(code))?.eval(&scope);
+    let loads = || loads.load(std::sync::atomic::Ordering::SeqCst);
+
+    // diamond imports, with keys.minsc imported by both the program and lib.minsc
+    let res =
+        eval(r#"import "keys.minsc"; import "lib.minsc"; [ with_alice(B), $alice ]"#).unwrap();
+    assert_eq!(
+        res.into_policy().unwrap().to_string(),
+        replace_dummy("thresh(2,and(pk(A),pk(B)),pk(A))")
+    );
+    assert_eq!(loads(), 1);
+
+    // modules are loaded once per program
+    eval(r#"import "keys.minsc"; import "keys.minsc" as k; k.$loads"#).unwrap();
+    assert_eq!(loads(), 2);
+}
+
+#[test]
+fn test_multisig() {
+    let eval_str = |code: &str| run(&replace_dummy(code)).unwrap().to_string();
+    assert_eq!(
+        eval_str("multi(2, [A, B, C])"),
+        replace_dummy("multi(2,A,B,C)")
+    );
+    test_desc("wsh(multi(2, A, B, C))", "wsh(multi(2,A,B,C))");
+    test_desc("sh(wsh(multi(1, [A, B])))", "sh(wsh(multi(1,A,B)))");
+    test_desc("sh(multi(2, [A, B]))", "sh(multi(2,A,B))");
+    test_desc(
+        "wsh(sortedmulti(2, [B, A, C]))",
+        "wsh(sortedmulti(2,B,A,C))",
+    );
+    test_desc(
+        "sh(wsh(sortedmulti(2, B, A)))",
+        "sh(wsh(sortedmulti(2,B,A)))",
+    );
+    test_desc("sh(sortedmulti(2, [B, A]))", "sh(sortedmulti(2,B,A))");
+    // sortedmulti defaults to wsh, the same as policies and miniscripts
+    assert_eq!(
+        eval_str("address(sortedmulti(1, [A, B]))"),
+        eval_str("address(wsh(sortedmulti(1, [A, B])))")
+    );
+    // the keys are sorted in the script, not in the descriptor
+    assert_eq!(
+        eval_str("script_pubkey(sortedmulti(1, [A, B]))"),
+        eval_str("script_pubkey(sortedmulti(1, [B, A]))")
+    );
+    assert_ne!(
+        eval_str("script_pubkey(multi(1, [A, B]))"),
+        eval_str("script_pubkey(multi(1, [B, A]))")
+    );
+
+    assert!(run(&replace_dummy("multi(3, [A, B])")).is_err());
+    assert!(run(&replace_dummy("multi(0, [A, B])")).is_err());
+    assert!(run(&replace_dummy("sortedmulti(3, [A, B])")).is_err());
+    assert!(run(&replace_dummy("multi(1, [A, pk(B)])")).is_err());
+}
+
+#[test]
+fn test_legacy_descriptors() {
+    let eval_str = |code: &str| run(&replace_dummy(code)).unwrap().to_string();
+    test_desc("pkh(A)", "pkh(A)");
+    test_desc("bare(A)", "pk(A)");
+    // bare descriptors are displayed as their plain miniscript
+    test_desc("bare(pk(A))", "pk(A)");
+    test_desc("bare(multi(1, A, B))", "multi(1,A,B)");
+    // policies are compiled under the legacy script context
+    test_desc("sh(pk(A) && pk(B))", "sh(and_v(v:pk(A),pk(B)))");
+    assert_eq!(
+        eval_str("sh(miniscript(pk(A) || (pk(B) && older(10))))"),
+        with_checksum(&format!(
+            "sh({})",
+            eval_str("miniscript(pk(A) || (pk(B) && older(10)))")
+        ))
+    );
+    assert!(
+        eval_str("address(pkh(A))").starts_with('m')
+            || eval_str("address(pkh(A))").starts_with('n')
+    );
+    assert!(eval_str("address(sh(pk(A) && pk(B)))").starts_with('2'));
+
+    // bare scripts only support pk() and multi(), and have no address
+    assert!(run(&replace_dummy("bare(pk(A) && older(10))")).is_err());
+    assert!(run(&replace_dummy("address(bare(A))")).is_err());
+    // legacy P2SH is limited to 520 bytes of script, enough for 15 keys but not 16
+    let keys = |n| vec!["A"; n].join(",");
+    assert!(run(&replace_dummy(&format!("sh(multi(1, {}))", keys(15)))).is_ok());
+    assert!(run(&replace_dummy(&format!("sh(multi(1, {}))", keys(16)))).is_err());
+    assert!(run(&replace_dummy(&format!("wsh(multi(1, {}))", keys(16)))).is_ok());
+}
+
+#[test]
+fn test_taproot_descriptors() {
+    let eval_str = |code: &str| run(&replace_dummy(code)).unwrap().to_string();
+    test_desc("tr(A)", "tr(A)");
+    test_desc("tr(A, pk(B))", "tr(A,pk(B))");
+    // policies are compiled into tapscript leaves, and arrays of two sub-trees form the branches
+    test_desc(
+        "tr(A, [pk(B), [pk(C) && older(10), 2 of [pk(B), pk(C), pk(D)]]])",
+        "tr(A,{pk(B),{and_v(v:pk(C),older(10)),thresh(2,pk(B),s:pk(C),s:pk(D))}})",
+    );
+    test_desc(
+        "tr(A, and_v(v:pk(B), older(10)))",
+        "tr(A,and_v(v:pk(B),older(10)))",
+    );
+    // bech32m addresses
+    assert!(eval_str("address(tr(A, pk(B)))").starts_with("tb1p"));
+    let mainnet = format!(
+        "address({}, _$$_RECKLESSLY_RISK_MY_BITCOINS_$$_)",
+        replace_dummy("tr(A)")
+    );
+    assert!(run(&mainnet).unwrap().to_string().starts_with("bc1p"));
+    assert!(eval_str("script_pubkey(tr(A))").starts_with("5120"));
+
+    // the internal key spends as an alternative to the leaves, using the cheaper key path
+    assert!(run(&replace_dummy(
+        "lift(tr(A, [pk(B), pk(C)])) == lift(pk(A) || pk(B) || pk(C))"
+    ))
+    .unwrap()
+    .into_bool()
+    .unwrap());
+    assert_eq!(
+        eval_str("cost(tr(A, pk(B)))").replace(&[' ', '\n'][..], ""),
+        replace_dummy(
+            r#"[["script_size",34],["op_count",1],["max_weight",146],["paths",[[[pk(A)],71],[[pk(B)],140]]]]"#
+        )
+    );
+
+    // multi() is not available in tapscript, and tree branches must have two sub-trees
+    assert!(run(&replace_dummy("tr(A, multi(1, B, C))")).is_err());
+    assert!(run(&replace_dummy("tr(A, [pk(B)])")).is_err());
+    assert!(run(&replace_dummy("tr(A, [pk(B), pk(C), pk(D)])")).is_err());
+    assert!(run(&replace_dummy("tr(pk(A))")).is_err());
+}
+
+#[test]
+fn test_taproot_policies() {
+    let eval_str = |code: &str| {
+        let res = run(&replace_dummy(code)).unwrap().to_string();
+        res.replace(&[' ', '\n'][..], "")
+    };
+    // the most likely key-only branch becomes the internal key
+    test_desc("taproot(pk(A))", "tr(A)");
+    test_desc("taproot(pk(A) || pk(B))", "tr(A,pk(B))");
+    test_desc("taproot(pk(A) || likely@pk(B))", "tr(B,pk(A))");
+    // the other branches are placed in a Huffman tree, with the more likely ones closer to the root
+    let policy =
+        "or(5@pk(A), 3@(pk(B) && older(10)), 1@(pk(C) && older(20)), 1@(pk(D) && older(30)))";
+    test_desc(
+        &format!("taproot({})", policy),
+        "tr(A,{and_v(v:pk(B),older(10)),{and_v(v:pk(C),older(20)),and_v(v:pk(D),older(30))}})",
+    );
+    assert_eq!(
+        eval_str(&format!("taproot_tree({})", policy)),
+        replace_dummy(
+            r#"[["internal_key",A],["tree",[and(pk(B),older(10)),[and(pk(C),older(20)),and(pk(D),older(30))]]]]"#
+        )
+    );
+    // equally likely branches keep their order
+    assert_eq!(
+        eval_str("taproot_tree(pk(A) || pk(B) || pk(C) || pk(D) || pk(E))"),
+        replace_dummy(r#"[["internal_key",A],["tree",[[pk(B),pk(C)],[pk(D),pk(E)]]]]"#)
+    );
+
+    // without a key-only branch, the internal key is the unspendable NUMS point
+    let desc = eval_str("taproot((pk(A) && older(10)) || (pk(B) && older(20)))");
+    assert!(desc.starts_with(&format!("tr({},", minsc::taproot::UNSPENDABLE_KEY)));
+    assert_eq!(
+        eval_str("lift(taproot((pk(A) && older(10)) || (pk(B) && older(20))))"),
+        replace_dummy("or(1@and(pk(A),older(10)),1@and(pk(B),older(20)))")
+    );
+    assert!(run(&replace_dummy(
+        "equivalent(taproot((pk(A) && older(10)) || pk(B)), (pk(A) && older(10)) || pk(B))"
+    ))
+    .unwrap()
+    .into_bool()
+    .unwrap());
+}
+
+#[test]
+fn test_miniscript_fragments() {
+    let eval_str = |code: &str| run(&replace_dummy(code)).unwrap().to_string();
+    let test_ms = |code: &str, expected: &str| assert_eq!(eval_str(code), replace_dummy(expected));
+
+    test_ms("and_v(v:pk(A), older(10))", "and_v(v:pk(A),older(10))");
+    test_ms(
+        "or_d(pk(A), and_v(v:pk(B), older(1 day)))",
+        "or_d(pk(A),and_v(v:pk(B),older(4194473)))",
+    );
+    test_ms(
+        "andor(pk(A), older(10), c:pk_h(B))",
+        "andor(pk(A),older(10),pkh(B))",
+    );
+    test_ms("and_b(pk(A), s:pk(B))", "and_b(pk(A),s:pk(B))");
+    test_ms("and_n(pk(A), sha256(H))", "and_n(pk(A),sha256(H))");
+    test_ms("or_i(c:pk_k(A), 0)", "u:pk(A)");
+    test_ms("tv:pk(A)", "tv:pk(A)");
+    // thresh() produces a miniscript when any of its subs is one
+    test_ms(
+        "thresh(2, pk(A), s:pk(B), sln:older(10))",
+        "thresh(2,pk(A),s:pk(B),sln:older(10))",
+    );
+    test_ms(
+        "2 of [pk(A), s:pk(B), a:pk(C)]",
+        "thresh(2,pk(A),s:pk(B),a:pk(C))",
+    );
+    // subexpressions that aren't fragments are compiled
+    test_ms(
+        "and_v(v:(pk(A) && pk(B)), older(10))",
+        "and_v(v:and_v(v:pk(A),pk(B)),older(10))",
+    );
+    test_desc(
+        "wsh(and_v(v:pk(A), older(10)))",
+        "wsh(and_v(v:pk(A),older(10)))",
+    );
+    assert!(eval_str("address(and_v(v:pk(A), older(10)))").starts_with("tb1"));
+
+    // type checking errors
+    let err_msg = |code: &str| run(&replace_dummy(code)).unwrap_err().to_string();
+    assert!(err_msg("and_v(pk(A), pk(B))").starts_with("in and_v(): Miniscript error:"));
+    assert!(err_msg("and_b(pk(A), pk(B))").starts_with("in and_b(): Miniscript error:"));
+    assert!(run(&replace_dummy("thresh(2, pk(A), pk_k(B))")).is_err());
+    assert!(run(&replace_dummy("or_d(pk(A), 2)")).is_err());
+    // not valid as a top-level script
+    assert!(run(&replace_dummy("wsh(v:pk(A))")).is_err());
+    assert!(run(&replace_dummy("address(pk_k(A))")).is_err());
+}
+
+#[test]
+fn test_parse_strings() {
+    let eval_str = |code: &str| run(&replace_dummy(code)).unwrap().to_string();
+    test(
+        r#"policy("or(pk(A),older(10))")"#,
+        "or(1@pk(A),1@older(10))",
+    );
+    test(
+        r#"policy("and(pk(A),pk(B))") || pk(C)"#,
+        "or(1@and(pk(A),pk(B)),1@pk(C))",
+    );
+    assert_eq!(
+        eval_str(r#"miniscript("and_v(v:pk(A),older(10))")"#),
+        replace_dummy("and_v(v:pk(A),older(10))")
+    );
+    assert_eq!(
+        eval_str(r#"or_d(miniscript("pk(A)"), and_v(v:pk(B), older(10)))"#),
+        replace_dummy("or_d(pk(A),and_v(v:pk(B),older(10)))")
+    );
+    test_desc(
+        r#"sh(descriptor("wsh(multi(1,A,B))"))"#,
+        "sh(wsh(multi(1,A,B)))",
+    );
+
+    // descriptor checksums are verified (test vector from BIP 380)
+    let desc = "sh(multi(2,[00000000/111'/222]xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL,xpub68NZiKmJWnxxS6aaHmn81bvJeTESw724CRDs6HbuccFQN9Ku14VQrADWgqbhhTHBaohPX4CjNLf9fq9MYo6oDaPPLPxSb7gwQN3ih19Zm4Y/0))";
+    assert_eq!(minsc::checksum::desc_checksum(desc).unwrap(), "tjg09x5t");
+    let res = run(&format!(r#"descriptor("{}#tjg09x5t")"#, desc)).unwrap();
+    assert_eq!(res.to_string(), format!("{}#tjg09x5t", desc));
+    assert_eq!(
+        run(&format!(r#"descriptor("{}#tjg09x5q")"#, desc))
+            .unwrap_err()
+            .to_string(),
+        "in descriptor(): Invalid descriptor checksum: tjg09x5q (expected tjg09x5t)"
+    );
+
+    assert!(run(&replace_dummy(r#"policy("or(pk(A)")"#)).is_err());
+    assert!(run(&replace_dummy(r#"miniscript("and_v(pk(A),older(10))")"#)).is_err());
+    assert!(run(&replace_dummy(r#"descriptor("wsh(pk(A)")"#)).is_err());
+}
+
+#[test]
+fn test_descriptor_checksums() {
+    let eval_str = |code: &str| run(&replace_dummy(code)).unwrap().into_string().unwrap();
+    assert_eq!(eval_str(r#"checksum("raw(deadbeef)")"#), "89f8spxm");
+    assert_eq!(
+        eval_str("checksum(wsh(pk(A)))"),
+        eval_str(r#"checksum("wsh(pk(A))")"#)
+    );
+    // displayed descriptors carry their checksum, and can be parsed back
+    let desc = run(&replace_dummy("sh(wsh(pk(A) && older(10)))"))
+        .unwrap()
+        .to_string();
+    assert!(desc.ends_with(&format!(
+        "#{}",
+        eval_str(&format!("checksum({:?})", &desc[..desc.len() - 9]))
+    )));
+    assert_eq!(
+        run(&format!("descriptor({:?})", desc)).unwrap().to_string(),
+        desc
+    );
+    assert!(run(r#"checksum("wsh(pk(é))")"#).is_err());
+}
+
+#[test]
+fn test_spending_paths() {
+    let paths = |code: &str| -> Vec<Vec<String>> {
+        let res = run(&replace_dummy(&format!("spending_paths({})", code))).unwrap();
+        res.into_array_elements()
+            .unwrap()
+            .into_iter()
+            .map(|path| {
+                let conditions = path.into_array_elements().unwrap().into_iter();
+                conditions.map(|c| c.to_string()).collect()
+            })
+            .collect()
+    };
+    let expected = |paths: &[&[&str]]| -> Vec<Vec<String>> {
+        paths
+            .iter()
+            .map(|path| path.iter().map(|c| replace_dummy(c)).collect())
+            .collect()
+    };
+
+    assert_eq!(
+        paths("pk(A) || (pk(B) && older(10))"),
+        expected(&[&["pk(A)"], &["pk(B)", "older(10)"]])
+    );
+    assert_eq!(
+        paths("pk(A) && (pk(B) || sha256(H))"),
+        expected(&[&["pk(A)", "pk(B)"], &["pk(A)", "sha256(H)"]])
+    );
+    assert_eq!(
+        paths("2 of [pk(A), pk(B), after(100)]"),
+        expected(&[
+            &["pk(A)", "pk(B)"],
+            &["pk(A)", "after(100)"],
+            &["pk(B)", "after(100)"]
+        ])
+    );
+    // redundant and duplicate paths are removed
+    assert_eq!(paths("pk(A) || (pk(A) && pk(B))"), expected(&[&["pk(A)"]]));
+    assert_eq!(paths("thresh(1, pk(A), pk(A))"), expected(&[&["pk(A)"]]));
+    assert_eq!(paths("pk(A) && pk(A)"), expected(&[&["pk(A)"]]));
+    assert_eq!(
+        paths("(pk(A) && pk(B)) || pk(C) || pk(A)"),
+        expected(&[&["pk(C)"], &["pk(A)"]])
+    );
+
+    // large thresholds are enumerated up to a limit
+    let hashes =
+        |n: usize| -> Vec<String> { (0..n).map(|i| format!("sha256({:064x})", i)).collect() };
+    let res = run(&format!(
+        "spending_paths(thresh(7, {}))",
+        hashes(14).join(", ")
+    ))
+    .unwrap();
+    assert_eq!(res.into_array_elements().unwrap().len(), 3432);
+    let err = run(&format!(
+        "spending_paths(thresh(8, {}))",
+        hashes(16).join(", ")
+    ))
+    .unwrap_err();
+    assert!(err.to_string().contains("Too many spending paths"));
+}
+
+#[test]
+fn test_cost() {
+    let cost = |code: &str| -> String {
+        let res = run(&replace_dummy(&format!("cost({})", code))).unwrap();
+        res.to_string().split_whitespace().collect()
+    };
+    let expected = |s: &str| replace_dummy(s).split_whitespace().collect::<String>();
+
+    assert_eq!(
+        cost("pk(A) || (pk(B) && older(10))"),
+        expected(
+            r#"[["script_size",75],["op_count",6],["max_weight",155],
+                ["paths",[[[pk(A)],154],[[pk(B),older(10)],155]]]]"#
+        )
+    );
+    // wpkh executes the implied P2PKH script, also when wrapped in sh
+    assert_eq!(
+        cost("wpkh(A)"),
+        expected(
+            r#"[["script_size",25],["op_count",4],["max_weight",112],["paths",[[[pk(A)],112]]]]"#
+        )
+    );
+    assert_eq!(
+        cost("sh(wpkh(A))"),
+        expected(
+            r#"[["script_size",25],["op_count",4],["max_weight",204],["paths",[[[pk(A)],204]]]]"#
+        )
+    );
+    assert_eq!(
+        cost("sh(wsh(sortedmulti(1, A, B)))"),
+        expected(
+            r#"[["script_size",71],["op_count",3],["max_weight",291],
+                ["paths",[[[thresh(1,pk(A),pk(B))],291]]]]"#
+        )
+    );
+    // legacy scripts are weighted 4 WU per byte
+    assert_eq!(
+        cost("pkh(A)"),
+        expected(
+            r#"[["script_size",25],["op_count",4],["max_weight",432],["paths",[[[pk(A)],432]]]]"#
+        )
+    );
+    assert_eq!(
+        cost("sh(pk(A) && pk(B))"),
+        expected(
+            r#"[["script_size",70],["op_count",2],["max_weight",872],["paths",[[[pk(A),pk(B)],872]]]]"#
+        )
+    );
+
+    // multisig is weighed once, other policies with too many paths are not weighed per path
+    let keys = |n: usize| -> String {
+        let keys: Vec<_> = (0..n).map(|i| format!("pk($xpub/{})", i)).collect();
+        keys.join(", ")
+    };
+    let xpub = "$xpub = xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw;";
+    let paths = |code: &str| -> Value {
+        let res = run(&format!("{} cost({})", xpub, code)).unwrap();
+        let mut entries = res.into_array_elements().unwrap();
+        let paths = entries.pop().unwrap().into_array_elements().unwrap();
+        paths.into_iter().nth(1).unwrap()
+    };
+    let multi = paths(&format!("wsh(thresh(8, {}))", keys(16)));
+    assert_eq!(multi.into_array_elements().unwrap().len(), 1);
+    let too_many = paths(&format!("wsh(thresh(8, {}) && pk($xpub/20))", keys(16)));
+    assert_eq!(too_many.to_string(), "false");
+}
+
+#[test]
+fn test_check() {
+    let check = |code: &str| -> Vec<[String; 3]> {
+        let res = run(&replace_dummy(&format!("check({})", code))).unwrap();
+        res.into_array_elements()
+            .unwrap()
+            .into_iter()
+            .map(|finding| {
+                let mut parts = finding.into_array_elements().unwrap().into_iter();
+                let mut next = || parts.next().unwrap().into_string().unwrap();
+                [next(), next(), next()]
+            })
+            .collect()
+    };
+    let kinds_and_culprits = |code: &str| -> Vec<(String, String)> {
+        check(code)
+            .into_iter()
+            .map(|[kind, _, culprit]| (kind, culprit))
+            .collect()
+    };
+    let expected = |findings: &[(&str, &str)]| -> Vec<(String, String)> {
+        findings
+            .iter()
+            .map(|(kind, culprit)| (kind.to_string(), replace_dummy(culprit)))
+            .collect()
+    };
+
+    assert!(check("pk(A) && (pk(B) || older(10))").is_empty());
+    assert!(check("wsh(sortedmulti(1, A, B))").is_empty());
+
+    assert_eq!(
+        kinds_and_culprits("pk(A) || (sha256(H) && older(10))"),
+        expected(&[("sanity", "older(10) && sha256(H)")])
+    );
+    assert_eq!(
+        kinds_and_culprits("pk(A) || (pk(A) && older(10))"),
+        expected(&[("sanity", "pk(A)")])
+    );
+    assert_eq!(
+        kinds_and_culprits("pk(A) && older(10) && older(1 day)"),
+        expected(&[("sanity", "thresh(3,pk(A),older(10),older(4194473))")])
+    );
+    // invalid timelocks are reported with the same error as older()
+    let older_err = run("older(65536)").unwrap_err().to_string();
+    let [_, message, culprit] = check(r#"pk(A) && policy("older(65536)")"#).remove(0);
+    assert!(older_err.contains(&message));
+    assert_eq!(culprit, "older(65536)");
+    assert_eq!(
+        kinds_and_culprits("pk(A) && (older(10) || sha256(H))"),
+        expected(&[("malleability", "or(1@older(10),1@sha256(H))")])
+    );
+    assert_eq!(
+        kinds_and_culprits("sh(or_i(pk(A), pk(B)))"),
+        expected(&[("malleability", "or_i(pk(A),pk(B))")])
+    );
+
+    // a 120-of-120 key threshold exceeds the op count, script size and witness elements limits
+    let xpub = "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw";
+    let code = format!(
+        "check(thresh(120, map([{}], |$n| if $n == 1 {{ pk({xpub}/$n) }} else {{ s:pk({xpub}/$n) }})))",
+        (1..=120).map(|n| n.to_string()).collect::<Vec<_>>().join(","),
+        xpub = xpub
+    );
+    let findings = run(&code).unwrap().into_array_elements().unwrap();
+    let messages: Vec<_> = findings
+        .into_iter()
+        .map(|finding| {
+            finding
+                .into_array_elements()
+                .unwrap()
+                .remove(1)
+                .into_string()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "Op count of 359 is above the consensus limit of 201",
+            "Witness script size of 4441 is above the standardness limit of 3600",
+            "Witness elements count of 121 is above the standardness limit of 100",
+        ]
+    );
+}
+
+#[test]
+fn test_lift() {
+    test(
+        "lift(wsh(pk(A) || (pk(B) && older(10))))",
+        "or(1@pk(A),1@and(pk(B),older(10)))",
+    );
+    test(
+        "lift(and_v(v:pk(A), or_d(pk(B), older(10))))",
+        "and(pk(A),or(1@pk(B),1@older(10)))",
+    );
+    test("lift(sh(wsh(sortedmulti(1, B, A))))", "or(1@pk(A),1@pk(B))");
+    test("lift(pkh(A))", "pk(A)");
+    // policies are normalized into a flattened and sorted form
+    test(
+        "lift(older(10) && (pk(B) && pk(A)))",
+        "thresh(3,pk(A),pk(B),older(10))",
+    );
+    test("lift(10@pk(B) || pk(A))", "or(1@pk(A),1@pk(B))");
+
+    // lifted descriptors can be compared with minsc policies
+    let desc = replace_dummy("wsh(andor(pk(A),older(10),pk(B)))");
+    let code = format!(
+        r#"lift(descriptor("{}")) == lift((pk(A) && older(10)) || pk(B))"#,
+        with_checksum(&desc)
+    );
+    assert!(run(&replace_dummy(&code)).unwrap().into_bool().unwrap());
+
+    // height-based and time-based timelocks cannot be combined in the same path
+    assert!(run("lift(older(10) && older(1 day))").is_err());
+}
+
+#[test]
+fn test_equivalent() {
+    let equivalent = |a: &str, b: &str| -> bool {
+        let code = replace_dummy(&format!("equivalent({}, {})", a, b));
+        run(&code).unwrap().into_bool().unwrap()
+    };
+    assert!(equivalent(
+        "pk(A) && (pk(B) || older(10))",
+        "(pk(A) && pk(B)) || (pk(A) && older(10))"
+    ));
+    assert!(equivalent(
+        "pk(A) && (pk(B) || older(10))",
+        "wsh(and_v(v:pk(A), or_d(pk(B), older(10))))"
+    ));
+    assert!(equivalent(
+        "2 of [pk(A), pk(B), pk(C)]",
+        "wsh(multi(2, C, A, B))"
+    ));
+    // stronger timelocks of the same type imply weaker ones
+    assert!(equivalent(
+        "pk(A) && older(10)",
+        "(pk(A) && older(10)) || (pk(A) && older(20))"
+    ));
+    assert!(!equivalent("pk(A) && older(10)", "pk(A) && older(20)"));
+    assert!(!equivalent("pk(A) && older(10)", "pk(A) && older(1 day)"));
+    assert!(!equivalent("pk(A) || pk(B)", "pk(A)"));
+
+    let lift = |code: &str| analyze::lift(run(&replace_dummy(code)).unwrap()).unwrap();
+    let cex = analyze::counterexample(
+        &lift("pk(A) && (pk(B) || older(10))"),
+        &lift("pk(A) && (pk(B) || sha256(H))"),
+    )
+    .unwrap()
+    .unwrap();
+    let conditions: Vec<_> = cex.conditions.iter().map(|c| c.to_string()).collect();
+    assert_eq!(conditions, [replace_dummy("pk(A)"), "older(10)".into()]);
+    assert!(cex.satisfies_first);
+}
+
+#[test]
+fn test_at_age_at_time() {
+    let policy = "pk(A) || (pk(B) && older(1 month)) || (pk(C) && older(6 months))";
+    test(&format!("at_age({}, 2 weeks)", policy), "pk(A)");
+    test(
+        &format!("at_age({}, 3 months)", policy),
+        "or(1@pk(A),1@and(pk(B),older(4199441)))",
+    );
+    test(
+        &format!("at_age({}, 1 year)", policy),
+        "thresh(1,pk(A),and(pk(B),older(4199441)),and(pk(C),older(4225122)))",
+    );
+    // by-blockheight timelocks are not satisfied by by-blocktime ages
+    test("at_age(pk(A) || (pk(B) && older(100)), 1 year)", "pk(A)");
+    test(
+        "at_age(pk(A) || (pk(B) && older(100)), 100)",
+        "or(1@pk(A),1@and(pk(B),older(100)))",
+    );
+    test(
+        "at_age(3 of [pk(A), pk(B), older(10), older(20)], 15)",
+        "and(pk(A),and(pk(B),older(10)))",
+    );
+    let res = run(&replace_dummy("at_age(pk(A) && older(10), 5)")).unwrap();
+    assert_eq!(res.into_policy().unwrap(), Policy::Unsatisfiable);
+    test("at_age(wsh(pk(A) || (pk(B) && older(10))), 5)", "pk(A)");
+
+    let policy = "pk(A) || (pk(B) && after(2025-01-01)) || (pk(C) && after(800000))";
+    test(&format!("at_time({}, 2024-06-01)", policy), "pk(A)");
+    test(
+        &format!("at_time({}, 2026-01-01)", policy),
+        "or(1@pk(A),1@and(pk(B),after(1735689600)))",
+    );
+    test(
+        &format!("at_time({}, 800000)", policy),
+        "or(1@pk(A),1@and(pk(C),after(800000)))",
+    );
+}
+
+#[test]
+fn test_can_spend() {
+    let can_spend = |resources: &str, expected: &str| {
+        let policy =
+            "(pk(A) && pk(D)) || (pk(B) && pk(C) && older(3 months)) || (pk(E) && older(1 year))";
+        let code = format!("can_spend({}, [{}])", policy, resources);
+        let res = run(&replace_dummy(&code)).unwrap().to_string();
+        assert_eq!(
+            res.split_whitespace().collect::<String>(),
+            replace_dummy(expected)
+        );
+    };
+    can_spend(
+        r#"["keys", [B, C]], ["age", 4 months]"#,
+        r#"[["spendable",true],["path",[pk(C),pk(B),older(4209713)]],["weight",359]]"#,
+    );
+    // the cheapest satisfying path is picked
+    can_spend(
+        r#"["keys", [A, D, B, C]], ["age", 4 months]"#,
+        r#"[["spendable",true],["path",[pk(D),pk(A)]],["weight",358]]"#,
+    );
+    can_spend(
+        r#"["keys", [B, C]]"#,
+        r#"[["spendable",false],["missing",[[older(4209713)]]]]"#,
+    );
+    can_spend(
+        r#"["keys", [B]]"#,
+        r#"[["spendable",false],["missing",[[pk(D),pk(A)],[pk(C),older(4209713)],[pk(E),older(4255898)]]]]"#,
+    );
+
+    let hash = "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925";
+    let preimage = "0000000000000000000000000000000000000000000000000000000000000000";
+    let code = format!(
+        r#"can_spend(pk(A) || (pk(B) && sha256({})), [["keys", [B]], ["preimages", [{}]]])"#,
+        hash, preimage
+    );
+    let res = run(&replace_dummy(&code)).unwrap().to_string();
+    assert_eq!(
+        res.split_whitespace().collect::<String>(),
+        replace_dummy(&format!(
+            r#"[["spendable",true],["path",[pk(B),sha256({})]],["weight",225]]"#,
+            hash
+        ))
+    );
+
+    // multisig is checked without enumerating its key combinations
+    let can_spend_multi = |resources: &str| -> String {
+        let code = format!("can_spend(wsh(multi(2, A, B, C)), [{}])", resources);
+        let res = run(&replace_dummy(&code)).unwrap().to_string();
+        res.split_whitespace().collect()
+    };
+    assert_eq!(
+        can_spend_multi(r#"["keys", [C, A]]"#),
+        replace_dummy(r#"[["spendable",true],["path",[pk(A),pk(C)]],["weight",258]]"#)
+    );
+    assert_eq!(
+        can_spend_multi(r#"["keys", [B]]"#),
+        replace_dummy(r#"[["spendable",false],["missing",[[thresh(1,pk(A),pk(C))]]]]"#)
+    );
+
+    assert!(run(&replace_dummy(r#"can_spend(pk(A), [["signatures", [A]]])"#)).is_err());
+}
+
+#[test]
+fn test_keys_hashes_timelocks() {
+    let extract = |code: &str| -> String {
+        let res = run(&replace_dummy(code)).unwrap();
+        res.to_string().split_whitespace().collect()
+    };
+    let expected = |s: &str| replace_dummy(s).split_whitespace().collect::<String>();
+
+    let policy =
+        "(pk(A) && sha256(H)) || (pk(B) && older(10)) || (pk(C) && after(100) && older(10))";
+    assert_eq!(extract(&format!("keys({})", policy)), expected("[A,B,C]"));
+    assert_eq!(extract(&format!("hashes({})", policy)), expected("[H]"));
+    assert_eq!(
+        extract(&format!("timelocks({})", policy)),
+        expected("[older(10),after(100)]")
+    );
+
+    // miniscripts and descriptors are walked too
+    assert_eq!(
+        extract(&format!("keys(wsh({}))", policy)),
+        expected("[A,B,C]")
+    );
+    assert_eq!(
+        extract("keys(sh(wsh(sortedmulti(1, B, A))))"),
+        expected("[B,A]")
+    );
+    assert_eq!(extract("keys(wpkh(A))"), expected("[A]"));
+    assert_eq!(extract("timelocks(pk(A))"), expected("[]"));
+    assert!(run("keys(5)").is_err());
+
+    // Rust API
+    let res = run(&replace_dummy(&format!("wsh({})", policy))).unwrap();
+    assert_eq!(res.keys().unwrap().len(), 3);
+    let hashes = res.hashes().unwrap();
+    assert_eq!(hashes.len(), 1);
+    assert_eq!(
+        Value::Hash(hashes[0].clone()).to_string(),
+        replace_dummy("H")
+    );
+    assert_eq!(
+        res.timelocks().unwrap(),
+        vec![Policy::Older(10), Policy::After(100)]
+    );
+}
+
+#[test]
+fn test_error_location() {
+    let code = "$a = 1 day;\n$b = older($a, 2);\n$b";
+    let err = run(code).unwrap_err();
+    assert_eq!(err.span().unwrap().line_col(code), (2, 6));
+
+    let code = "fn f($k) = pk($k) && older(#);\nf(A)";
+    let err = run(code).unwrap_err();
+    assert_eq!(
+        err.format_with_source(code, "test.minsc"),
+        "test.minsc:1:28: Parser error: Invalid token\n  fn f($k) = pk($k) && older(#);\n                             ^"
+    );
+
+    // spans that don't fall on a char boundary are clamped to the preceding one
+    let code = "\"é\"";
+    assert_eq!(Span::new(2, 3).line_col(code), (1, 2));
+    assert_eq!(
+        minsc::Error::InvalidArguments
+            .at(Span::new(2, 3))
+            .format_with_source(code, "test.minsc"),
+        "test.minsc:1:2: Invalid arguments\n  \"é\"\n   ^"
+    );
+}
+
 fn replace_dummy(s: &str) -> String {
     s.replace(
         "A",
@@ -2,17 +2,19 @@ import CodeMirror from 'codemirror'
 
 CodeMirror.defineSimpleMode("minsc",{
   start: [
+    {regex: /"(?:[^\\"]|\\.)*"/, token: "string"},
     {regex: /\d+(\.\d+)?\s+(years?|months?|weeks?|days?|hours?|min(?:ute)?s?|sec(ond)?s?)\b/, token: "number"},
     {regex: /\d+\s+blocks?\b/, token: "number"},
     {regex: /\d{4}-\d{1,2}-\d{1,2}(\s+\d{1,2}:\d{1,2})?\b/, token: "number"},
     {regex: /\b([a-f0-9]{8}|[a-f0-9]{40,130}|[xt]pub[0-9a-zA-Z]{100,120})\b/, token: "number"},
     {regex: /\b\d+\b/, token: "number"},
     {regex: /\b(fn)(\s+)([$a-zA-Z_]\w*)/, token: ["keyword", null, "def"]},
-    {regex: /\b(of|return|let|heightwise|likely)\b/, token: "keyword"},
+    {regex: /\b(of|return|let|heightwise|likely|if|else|true|false|import|as)\b/, token: "keyword"},
     {regex: /\/\/.*/, token: "comment"},
-    {regex: /[-+\/*=<>!;@]+|&&|\|\|/, token: "operator"}, // */
+    {regex: /\b[asctdvjnlu]+:/, token: "operator"},
+    {regex: /[-+\/*%=<>!;@]+|&&|\|\||\b(?:and|or)\b(?!\s*\()/, token: "operator"}, // */
     {regex: /\b(or|and|thresh)\b/, token: "builtin"},
-    {regex: /\b(pk|older|after|(sha|hash)256|(ripemd|hash)160|any|all|prob|wsh|wpkh|sh|miniscript|address|script_pubkey|script_witness)\b/, token: "builtin"},
+    {regex: /\b(pk|older|after|(sha|hash)256|(ripemd|hash)160|any|all|prob|wsh|wpkh|sh|pkh|bare|multi|sortedmulti|pk_[kh]|and_[vbn]|andor|or_[bcdi]|miniscript|policy|descriptor|checksum|spending_paths|cost|check|lift|equivalent|at_age|at_time|can_spend|keys|hashes|timelocks|address|script_pubkey|script_witness|map|filter|flat_map|reduce|fold|zip)\b/, token: "builtin"},
     {regex: /([$a-zA-Z_][$a-zA-Z_0-9]*)\s*(\()/, token: ["atom", null]},
     {regex: /[$a-zA-Z_][$a-zA-Z_0-9]*\b/, token: "variable-3"},
     {regex: /\.\d+\b/, token: "property"},
@@ -23,6 +23,7 @@ const error_el = document.querySelector('#error')
     , output_el_script = document.querySelector('#output-script')
     , output_el_other = document.querySelector('#output-other')
     , output_el_address = document.querySelector('#output-address')
+    , output_el_cost = document.querySelector('#output-cost')
 
 const initial_code = location.hash.startsWith('#c=') && location.hash.length > 3
                      ? decodeURIComponent(location.hash.substr(3))
@@ -47,15 +48,29 @@ worker.addEventListener('message', ({ data }) => {
     output_el_script.style.display = r.script_asm ? 'block' : 'none'
     output_el_address.style.display = r.address ? 'block' : 'none'
     output_el_other.style.display = r.other ? 'block' : 'none'
+    output_el_cost.style.display = r.path_weights ? 'block' : 'none'
 
     output_policy.setValue(r.policy || '')
     output_miniscript.setValue(r.descriptor || r.miniscript || '')
     output_script.setValue(r.script_asm || '')
     output_other.setValue(r.other || '')
     output_el_address.querySelector('span').innerText = r.address || ''
+    output_el_cost.querySelector('pre').innerText = r.path_weights ? formatCost(r) : ''
   }
 })
 
+function formatCost(r) {
+  const wu = w => w == null ? 'unsatisfiable' : `${w} WU`
+  return [
+    `Script size: ${r.script_size} bytes`,
+    `Op count: ${r.op_count == null ? 'n/a' : r.op_count}`,
+    `Max satisfaction weight: ${wu(r.max_weight)}`,
+    '',
+    ...r.path_weights.map(([path, weight]) => `${path || 'true'}: ${wu(weight)}`),
+    ...(r.too_many_paths ? ['Too many spending paths to weigh individually'] : []),
+  ].join('\n')
+}
+
 function update(source) {
   clearErrorMark()
 